@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use iiif_forager::image::exif::Exif;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(exif) = Exif::parse(data) {
+        let _ = exif.orientation();
+        for label in exif.labels() {
+            let _ = label.to_string();
+        }
+    }
+});
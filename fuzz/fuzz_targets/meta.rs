@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use iiif_forager::meta::{Format, Meta};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Meta::from_reader(data, Format::JSON);
+    let _ = Meta::from_reader(data, Format::YAML);
+});
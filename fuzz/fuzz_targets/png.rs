@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use iiif_forager::image::png::{read_png, ReadOptions};
+use std::io::Cursor;
+
+// The first byte picks the options, so every way of reading is covered
+fuzz_target!(|data: &[u8]| {
+    let (flags, data) = match data.split_first() {
+        Some((flags, data)) => (*flags, data),
+        None => return,
+    };
+    let options = ReadOptions {
+        check_integrity: flags & 1 != 0,
+        verify_image_data: flags & 2 != 0,
+        text_after_data: flags & 4 != 0,
+    };
+    if let Ok(png) = read_png(&mut Cursor::new(data), &options) {
+        assert!(!png.chunks.is_empty());
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use iiif_forager::image::xmp;
+
+fuzz_target!(|data: &[u8]| {
+    for label in xmp::labels(&String::from_utf8_lossy(data)) {
+        let _ = label.to_string();
+    }
+});
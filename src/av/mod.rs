@@ -0,0 +1,5 @@
+//! Audio and video containers, read for their duration and video size.
+pub mod mp3;
+pub mod mp4;
+pub mod wav;
+pub mod webm;
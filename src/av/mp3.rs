@@ -0,0 +1,191 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec};
+
+// How far to look for the first frame after the ID3 tag
+const MAX_SYNC_SEARCH: u64 = 64 * 1024;
+const ID3V1_LENGTH: u64 = 128;
+
+// Bit rates in kbit/s by bit rate index 1 to 14
+const BIT_RATES: [[u16; 14]; 5] = [
+    // MPEG 1, layer I, II and III
+    [
+        32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    // MPEG 2 and 2.5, layer I and layers II and III
+    [
+        32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// Duration of an MPEG audio file, from the Xing, Info or VBRI header in
+/// the first frame or, for constant bit rates, from the file size.
+#[derive(Debug, Default, PartialEq)]
+pub struct MP3 {
+    pub duration: f64,
+    pub sample_rate: u32,
+}
+
+impl MP3 {
+    pub fn load(path: &PathBuf) -> std::io::Result<MP3> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_mp3(&mut file)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct FrameHeader {
+    mpeg1: bool,
+    layer: u8,
+    bit_rate: u32,
+    sample_rate: u32,
+    mono: bool,
+}
+
+impl FrameHeader {
+    fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+        if bytes.len() < 4 || bytes[0] != 0xff || bytes[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+        let version = (bytes[1] >> 3) & 0x03;
+        let layer = match (bytes[1] >> 1) & 0x03 {
+            0 => return None,
+            bits => 4 - bits,
+        };
+        let bit_rate_index = usize::from(bytes[2] >> 4);
+        let sample_rate_index = usize::from((bytes[2] >> 2) & 0x03);
+        // free format is not supported, 15 is invalid
+        if version == 1 || bit_rate_index == 0 || bit_rate_index == 15 || sample_rate_index == 3 {
+            return None;
+        }
+        let mpeg1 = version == 3;
+        let table = match (mpeg1, layer) {
+            (true, layer) => usize::from(layer - 1),
+            (false, 1) => 3,
+            (false, _) => 4,
+        };
+        let sample_rate = [44100u32, 48000, 32000][sample_rate_index]
+            >> match version {
+                3 => 0,
+                2 => 1,
+                _ => 2,
+            };
+        Some(FrameHeader {
+            mpeg1,
+            layer,
+            bit_rate: u32::from(BIT_RATES[table][bit_rate_index - 1]) * 1000,
+            sample_rate,
+            mono: bytes[3] >> 6 == 3,
+        })
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.mpeg1) {
+            (1, _) => 384,
+            (2, _) | (3, true) => 1152,
+            _ => 576,
+        }
+    }
+
+    /// Number of frames from a Xing, Info or VBRI header in the frame.
+    fn frame_count(&self, frame: &[u8]) -> Option<u32> {
+        let side_info = match (self.mpeg1, self.mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        };
+        let xing = frame.get(4 + side_info..4 + side_info + 12)?;
+        if &xing[..4] == b"Xing" || &xing[..4] == b"Info" {
+            // the frame count is the first optional field
+            if xing[7] & 0x01 != 0 {
+                return Some(u32::from_be_bytes([xing[8], xing[9], xing[10], xing[11]]));
+            }
+            return None;
+        }
+        let vbri = frame.get(36..54)?;
+        if &vbri[..4] == b"VBRI" {
+            return Some(u32::from_be_bytes([vbri[14], vbri[15], vbri[16], vbri[17]]));
+        }
+        None
+    }
+}
+
+pub fn read_mp3<R: Read + Seek>(reader: &mut R) -> std::io::Result<MP3> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut start = 0;
+    let mut id3 = [0u8; 10];
+    if file_length >= 10 {
+        reader.read_exact(&mut id3)?;
+        if id3.starts_with(b"ID3") {
+            let size = id3[6..10]
+                .iter()
+                .fold(0u64, |size, byte| size << 7 | u64::from(byte & 0x7f));
+            let footer = if id3[5] & 0x10 != 0 { 10 } else { 0 };
+            start = 10 + size + footer;
+        }
+    }
+    if start >= file_length {
+        return Err(invalid_data("mp3", "missing audio frame"));
+    }
+    reader.seek(SeekFrom::Start(start))?;
+    let window = read_vec(reader, MAX_SYNC_SEARCH.min(file_length - start))?;
+    let (offset, header) = (0..window.len())
+        .find_map(|i| FrameHeader::parse(&window[i..]).map(|header| (i, header)))
+        .ok_or_else(|| invalid_data("mp3", "missing audio frame"))?;
+
+    let duration = match header.frame_count(&window[offset..]) {
+        Some(frames) => {
+            f64::from(frames) * f64::from(header.samples_per_frame())
+                / f64::from(header.sample_rate)
+        }
+        None => {
+            let mut audio_length = file_length - start - offset as u64;
+            if audio_length > ID3V1_LENGTH {
+                reader.seek(SeekFrom::Start(file_length - ID3V1_LENGTH))?;
+                let mut tag = [0u8; 3];
+                reader.read_exact(&mut tag)?;
+                if &tag == b"TAG" {
+                    audio_length -= ID3V1_LENGTH;
+                }
+            }
+            audio_length as f64 * 8.0 / f64::from(header.bit_rate)
+        }
+    };
+    Ok(MP3 {
+        duration,
+        sample_rate: header.sample_rate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::av::mp3::read_mp3;
+
+    #[test]
+    fn constant_and_variable_bit_rate() {
+        // MPEG 1 layer III, 128 kbit/s, 44.1 kHz, stereo: 16000 bytes per second
+        let mut data = vec![0u8; 32000];
+        data[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        let mp3 = read_mp3(&mut Cursor::new(data.clone())).unwrap();
+        assert_eq!(mp3.duration, 2.0);
+        assert_eq!(mp3.sample_rate, 44100);
+
+        // Xing header after 32 bytes of side information: 100 frames
+        data[36..48].copy_from_slice(b"Xing\0\0\0\x01\0\0\0\x64");
+        let mp3 = read_mp3(&mut Cursor::new(data)).unwrap();
+        assert_eq!(mp3.duration, 100.0 * 1152.0 / 44100.0);
+    }
+}
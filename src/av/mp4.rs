@@ -0,0 +1,293 @@
+use nom::{
+    bytes::complete::take,
+    multi::count,
+    number::complete::{be_i32, be_u32, be_u64, be_u8},
+    IResult,
+};
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec, Orientation};
+
+// Header boxes are small, sample tables are never read
+const MAX_HEADER_BOX_LENGTH: u64 = 4096;
+const FIXED_ONE: i32 = 0x0001_0000;
+
+/// Duration and video size of an MP4 (ISO base media) file, read from the
+/// movie and track headers in the `moov` box.
+#[derive(Debug, Default, PartialEq)]
+pub struct MP4 {
+    pub duration: f64,
+    /// Size of the first video track, 0 if there is none
+    pub width: u32,
+    pub height: u32,
+    /// Rotation from the transformation matrix of the video track
+    pub orientation: Orientation,
+}
+
+impl MP4 {
+    pub fn load(path: &PathBuf) -> std::io::Result<MP4> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_mp4(&mut file)
+    }
+}
+
+#[derive(Debug)]
+struct BoxHeader {
+    box_type: [u8; 4],
+    // position and end of the box content
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug, Default)]
+struct Track {
+    handler: [u8; 4],
+    width: u32,
+    height: u32,
+    orientation: Orientation,
+}
+
+pub fn read_mp4<R: Read + Seek>(reader: &mut R) -> std::io::Result<MP4> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    let moov = find_box(reader, 0, file_length, b"moov")?
+        .ok_or_else(|| invalid_data("mp4", "missing moov box"))?;
+
+    let mut timescale = 0;
+    let mut duration = 0;
+    let mut tracks = Vec::new();
+    for child in children(reader, &moov)? {
+        match &child.box_type {
+            b"mvhd" => {
+                let data = read_box(reader, &child)?;
+                let (_, header) =
+                    parse_movie_header(&data).map_err(|_| invalid_data("mp4", "mvhd"))?;
+                timescale = header.0;
+                duration = header.1;
+            }
+            // fragmented files may only give the duration in the extends header
+            b"mvex" if duration == 0 => {
+                if let Some(mehd) = find_box(reader, child.start, child.end, b"mehd")? {
+                    let data = read_box(reader, &mehd)?;
+                    let (_, fragments) =
+                        parse_extends_header(&data).map_err(|_| invalid_data("mp4", "mehd"))?;
+                    duration = fragments;
+                }
+            }
+            b"trak" => tracks.push(read_track(reader, &child)?),
+            _ => (),
+        }
+    }
+    if timescale == 0 {
+        return Err(invalid_data("mp4", "missing movie header"));
+    }
+
+    let mut mp4 = MP4 {
+        duration: duration as f64 / f64::from(timescale),
+        ..MP4::default()
+    };
+    if let Some(video) = tracks.iter().find(|track| &track.handler == b"vide") {
+        mp4.width = video.width;
+        mp4.height = video.height;
+        mp4.orientation = video.orientation;
+    }
+    Ok(mp4)
+}
+
+fn read_track<R: Read + Seek>(reader: &mut R, trak: &BoxHeader) -> std::io::Result<Track> {
+    let mut track = Track::default();
+    for child in children(reader, trak)? {
+        match &child.box_type {
+            b"tkhd" => {
+                let data = read_box(reader, &child)?;
+                let (_, (width, height, orientation)) =
+                    parse_track_header(&data).map_err(|_| invalid_data("mp4", "tkhd"))?;
+                track.width = width;
+                track.height = height;
+                track.orientation = orientation;
+            }
+            b"mdia" => {
+                if let Some(hdlr) = find_box(reader, child.start, child.end, b"hdlr")? {
+                    let data = read_box(reader, &hdlr)?;
+                    // version and flags, pre_defined, handler_type
+                    if let Some(handler) = data.get(8..12) {
+                        track.handler.copy_from_slice(handler);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(track)
+}
+
+fn read_box_header<R: Read + Seek>(
+    reader: &mut R,
+    position: u64,
+    end: u64,
+) -> std::io::Result<BoxHeader> {
+    reader.seek(SeekFrom::Start(position))?;
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let length = u64::from(u32::from_be_bytes([
+        header[0], header[1], header[2], header[3],
+    ]));
+    let (header_length, box_length) = match length {
+        0 => (8, end - position),
+        1 => {
+            let mut extended = [0u8; 8];
+            reader.read_exact(&mut extended)?;
+            (16, u64::from_be_bytes(extended))
+        }
+        length => (8, length),
+    };
+    if box_length < header_length || box_length > end - position {
+        return Err(invalid_data("mp4", "box length exceeds parent"));
+    }
+    let mut box_type = [0u8; 4];
+    box_type.copy_from_slice(&header[4..]);
+    Ok(BoxHeader {
+        box_type,
+        start: position + header_length,
+        end: position + box_length,
+    })
+}
+
+/// Finds the first box of a type between `start` and `end`, seeking over
+/// all others.
+fn find_box<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+    box_type: &[u8; 4],
+) -> std::io::Result<Option<BoxHeader>> {
+    let mut position = start;
+    while position + 8 <= end {
+        let header = read_box_header(reader, position, end)?;
+        if &header.box_type == box_type {
+            return Ok(Some(header));
+        }
+        position = header.end;
+    }
+    Ok(None)
+}
+
+fn children<R: Read + Seek>(reader: &mut R, parent: &BoxHeader) -> std::io::Result<Vec<BoxHeader>> {
+    let mut boxes = Vec::new();
+    let mut position = parent.start;
+    while position + 8 <= parent.end {
+        let header = read_box_header(reader, position, parent.end)?;
+        position = header.end;
+        boxes.push(header);
+    }
+    Ok(boxes)
+}
+
+fn read_box<R: Read + Seek>(reader: &mut R, header: &BoxHeader) -> std::io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(header.start))?;
+    read_vec(
+        reader,
+        (header.end - header.start).min(MAX_HEADER_BOX_LENGTH),
+    )
+}
+
+/// Timescale and duration
+fn parse_movie_header(data: &[u8]) -> IResult<&[u8], (u32, u64)> {
+    let (data, version) = be_u8(data)?;
+    let (data, _flags) = take(3usize)(data)?;
+    if version == 1 {
+        let (data, _times) = take(16usize)(data)?;
+        let (data, timescale) = be_u32(data)?;
+        let (data, duration) = be_u64(data)?;
+        Ok((data, (timescale, duration)))
+    } else {
+        let (data, _times) = take(8usize)(data)?;
+        let (data, timescale) = be_u32(data)?;
+        let (data, duration) = be_u32(data)?;
+        Ok((data, (timescale, u64::from(duration))))
+    }
+}
+
+fn parse_extends_header(data: &[u8]) -> IResult<&[u8], u64> {
+    let (data, version) = be_u8(data)?;
+    let (data, _flags) = take(3usize)(data)?;
+    if version == 1 {
+        be_u64(data)
+    } else {
+        let (data, duration) = be_u32(data)?;
+        Ok((data, u64::from(duration)))
+    }
+}
+
+/// Width, height and the rotation of the transformation matrix
+fn parse_track_header(data: &[u8]) -> IResult<&[u8], (u32, u32, Orientation)> {
+    let (data, version) = be_u8(data)?;
+    let (data, _flags) = take(3usize)(data)?;
+    // times, track id, reserved and duration
+    let (data, _) = take(if version == 1 { 32usize } else { 20 })(data)?;
+    // reserved, layer, alternate group, volume, reserved
+    let (data, _) = take(16usize)(data)?;
+    let (data, matrix) = count(be_i32, 9)(data)?;
+    let (data, width) = be_u32(data)?;
+    let (data, height) = be_u32(data)?;
+    let rotation = match (matrix[0], matrix[1], matrix[3], matrix[4]) {
+        (0, FIXED_ONE, b, 0) if b == -FIXED_ONE => 90,
+        (a, 0, 0, d) if a == -FIXED_ONE && d == -FIXED_ONE => 180,
+        (0, b, FIXED_ONE, 0) if b == -FIXED_ONE => 270,
+        _ => 0,
+    };
+    // sizes are 16.16 fixed point
+    Ok((
+        data,
+        (
+            width >> 16,
+            height >> 16,
+            Orientation::default().rotate(rotation),
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::av::mp4::read_mp4;
+    use crate::image::Orientation;
+
+    fn mp4_box(box_type: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut data = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(content);
+        data
+    }
+
+    #[test]
+    fn duration_and_rotated_video() {
+        // version 0, times, timescale 1000, duration 90500
+        let mut mvhd = vec![0u8; 12];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&90500u32.to_be_bytes());
+        mvhd.resize(100, 0);
+
+        let mut tkhd = vec![0u8; 40];
+        for value in &[0, 0x10000, 0, -0x10000, 0, 0, 0, 0, 0x4000_0000i32] {
+            tkhd.extend_from_slice(&value.to_be_bytes());
+        }
+        tkhd.extend_from_slice(&(1920u32 << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(1080u32 << 16).to_be_bytes());
+        let hdlr = mp4_box(b"hdlr", b"\0\0\0\0\0\0\0\0vide");
+        let trak = [mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &hdlr)].concat();
+        let moov = [mp4_box(b"mvhd", &mvhd), mp4_box(b"trak", &trak)].concat();
+
+        let mut data = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+        data.extend(mp4_box(b"mdat", &[0u8; 64]));
+        data.extend(mp4_box(b"moov", &moov));
+        let mp4 = read_mp4(&mut Cursor::new(data)).unwrap();
+        assert_eq!(mp4.duration, 90.5);
+        assert_eq!((mp4.width, mp4.height), (1920, 1080));
+        assert_eq!(mp4.orientation, Orientation::from_exif(6));
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec};
+
+// Only the fixed fields of the format chunk are read
+const FORMAT_LENGTH: u64 = 16;
+
+/// Format and duration of a RIFF WAVE file.
+#[derive(Debug, Default, PartialEq)]
+pub struct WAV {
+    pub duration: f64,
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+impl WAV {
+    pub fn load(path: &PathBuf) -> std::io::Result<WAV> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_wav(&mut file)
+    }
+}
+
+/// Walks the chunks up to the format and data chunks. Sample data is
+/// skipped, its length is taken from the data chunk header.
+pub fn read_wav<R: Read + Seek>(reader: &mut R) -> std::io::Result<WAV> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return Err(invalid_data("wav", "missing RIFF WAVE header"));
+    }
+
+    let mut wav = WAV::default();
+    let mut byte_rate = 0;
+    let mut data_length = None;
+    let mut position = 12;
+    while position + 8 <= file_length && (byte_rate == 0 || data_length.is_none()) {
+        let mut chunk_header = [0u8; 8];
+        reader.read_exact(&mut chunk_header)?;
+        let length = u64::from(u32::from_le_bytes([
+            chunk_header[4],
+            chunk_header[5],
+            chunk_header[6],
+            chunk_header[7],
+        ]));
+        // streamed files may leave the length open
+        let available = file_length - position - 8;
+        match &chunk_header[..4] {
+            b"fmt " => {
+                if length < FORMAT_LENGTH {
+                    return Err(invalid_data("wav", "short format chunk"));
+                }
+                let format = read_vec(reader, FORMAT_LENGTH)?;
+                wav.channels = u16::from_le_bytes([format[2], format[3]]);
+                wav.sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+                byte_rate = u32::from_le_bytes([format[8], format[9], format[10], format[11]]);
+            }
+            b"data" => data_length = Some(length.min(available)),
+            _ => (),
+        }
+        // chunks are padded to an even length
+        position += 8 + length + (length & 1);
+        reader.seek(SeekFrom::Start(position))?;
+    }
+    match data_length {
+        Some(length) if byte_rate > 0 => {
+            wav.duration = length as f64 / f64::from(byte_rate);
+            Ok(wav)
+        }
+        Some(_) => Err(invalid_data("wav", "missing format chunk")),
+        None => Err(invalid_data("wav", "missing data chunk")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::av::wav::read_wav;
+
+    #[test]
+    fn duration_from_data_length() {
+        let mut data = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        // PCM, mono, 8000 Hz, 16000 bytes per second, 2 bytes per sample, 16 bits
+        data.extend_from_slice(&[1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x80, 0x3e, 0, 0, 2, 0, 16, 0]);
+        data.extend_from_slice(b"data\x00\x7d\0\0");
+        data.resize(data.len() + 32000, 0);
+        let wav = read_wav(&mut Cursor::new(data)).unwrap();
+        assert_eq!((wav.channels, wav.sample_rate), (1, 8000));
+        assert_eq!(wav.duration, 2.0);
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec};
+
+pub const EBML_SIGNATURE: &[u8] = &[0x1a, 0x45, 0xdf, 0xa3];
+
+const EBML: u32 = 0x1a45_dfa3;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_a966;
+const TIMECODE_SCALE: u32 = 0x2a_d7b1;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654_ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_TYPE: u32 = 0x83;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const CLUSTER: u32 = 0x1f43_b675;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+// Default timecode scale, in nanoseconds
+const NANOSECONDS_PER_TICK: u64 = 1_000_000;
+const MAX_HEADER_LENGTH: u64 = 1024 * 1024;
+
+/// Duration and video size of a WebM (Matroska) file, read from the
+/// segment information and the track entries.
+#[derive(Debug, Default, PartialEq)]
+pub struct WebM {
+    pub duration: f64,
+    /// Size of the first video track, 0 if there is none
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WebM {
+    pub fn load(path: &PathBuf) -> std::io::Result<WebM> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_webm(&mut file)
+    }
+}
+
+/// Walks the top level elements of the segment up to the first cluster.
+pub fn read_webm<R: Read + Seek>(reader: &mut R) -> std::io::Result<WebM> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let (id, size) = read_element_header(reader)?;
+    if id != EBML {
+        return Err(invalid_data("webm", "missing EBML header"));
+    }
+    let size = size.ok_or_else(|| invalid_data("webm", "EBML header without size"))?;
+    reader.seek(SeekFrom::Current(size as i64))?;
+    let (id, size) = read_element_header(reader)?;
+    if id != SEGMENT {
+        return Err(invalid_data("webm", "missing segment"));
+    }
+    let mut position = reader.stream_position()?;
+    // live recordings leave the segment size unknown
+    let end = size.map_or(file_length, |size| (position + size).min(file_length));
+
+    let mut webm = WebM::default();
+    let mut duration = None;
+    let mut tick = NANOSECONDS_PER_TICK;
+    let mut has_tracks = false;
+    while position < end && (duration.is_none() || !has_tracks) {
+        reader.seek(SeekFrom::Start(position))?;
+        let (id, size) = read_element_header(reader)?;
+        let size = match size {
+            Some(size) => size,
+            None => break,
+        };
+        let content_start = reader.stream_position()?;
+        match id {
+            INFO | TRACKS if size <= MAX_HEADER_LENGTH => {
+                let data = read_vec(reader, size)?;
+                for (child, content) in elements(&data)? {
+                    match (id, child) {
+                        (INFO, TIMECODE_SCALE) => tick = unsigned(content),
+                        (INFO, DURATION) => duration = Some(float(content)?),
+                        (TRACKS, TRACK_ENTRY) if webm.width == 0 => {
+                            read_track_entry(content, &mut webm)?
+                        }
+                        _ => (),
+                    }
+                }
+                has_tracks |= id == TRACKS;
+            }
+            CLUSTER => break,
+            _ => (),
+        }
+        position = content_start + size;
+    }
+    match duration {
+        Some(ticks) => {
+            webm.duration = ticks * tick as f64 / 1e9;
+            Ok(webm)
+        }
+        None => Err(invalid_data("webm", "missing duration")),
+    }
+}
+
+fn read_track_entry(data: &[u8], webm: &mut WebM) -> std::io::Result<()> {
+    let entry = elements(data)?;
+    let is_video = entry
+        .iter()
+        .any(|(id, content)| *id == TRACK_TYPE && unsigned(content) == TRACK_TYPE_VIDEO);
+    if !is_video {
+        return Ok(());
+    }
+    if let Some((_, video)) = entry.iter().find(|(id, _)| *id == VIDEO) {
+        for (id, content) in elements(video)? {
+            match id {
+                PIXEL_WIDTH => webm.width = unsigned(content) as u32,
+                PIXEL_HEIGHT => webm.height = unsigned(content) as u32,
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads an element id (with its length marker) and size (without it).
+/// The size is None if it is unknown.
+fn read_element_header<R: Read>(reader: &mut R) -> std::io::Result<(u32, Option<u64>)> {
+    let (id, _length) = read_vint(reader, 4)?;
+    let (size, length) = read_vint(reader, 8)?;
+    let marker = 1u64 << (7 * length);
+    let size = size & (marker - 1);
+    if size == marker - 1 {
+        Ok((id as u32, None))
+    } else {
+        Ok((id as u32, Some(size)))
+    }
+}
+
+/// Reads a variable length integer with its length marker.
+fn read_vint<R: Read>(reader: &mut R, max_length: u32) -> std::io::Result<(u64, u32)> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    let length = first[0].leading_zeros() + 1;
+    if length > max_length {
+        return Err(invalid_data("webm", "invalid variable length integer"));
+    }
+    let mut value = u64::from(first[0]);
+    for _ in 1..length {
+        reader.read_exact(&mut first)?;
+        value = value << 8 | u64::from(first[0]);
+    }
+    Ok((value, length))
+}
+
+/// Splits element content into child elements.
+fn elements(mut data: &[u8]) -> std::io::Result<Vec<(u32, &[u8])>> {
+    let mut elements = Vec::new();
+    while !data.is_empty() {
+        let mut cursor = std::io::Cursor::new(data);
+        let (id, size) = read_element_header(&mut cursor)?;
+        let start = cursor.position() as usize;
+        let size = size.ok_or_else(|| invalid_data("webm", "child element without size"))?;
+        let end = start
+            .checked_add(size as usize)
+            .filter(|end| *end <= data.len())
+            .ok_or_else(|| invalid_data("webm", "element exceeds parent"))?;
+        elements.push((id, &data[start..end]));
+        data = &data[end..];
+    }
+    Ok(elements)
+}
+
+fn unsigned(data: &[u8]) -> u64 {
+    data.iter()
+        .take(8)
+        .fold(0, |value, byte| value << 8 | u64::from(*byte))
+}
+
+fn float(data: &[u8]) -> std::io::Result<f64> {
+    match data.len() {
+        4 => Ok(f64::from(f32::from_be_bytes([
+            data[0], data[1], data[2], data[3],
+        ]))),
+        8 => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(data);
+            Ok(f64::from_be_bytes(bytes))
+        }
+        _ => Err(invalid_data("webm", "invalid float")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::av::webm::read_webm;
+
+    #[test]
+    fn duration_and_video_size() {
+        let mut data = vec![0x1a, 0x45, 0xdf, 0xa3, 0x84, 0x42, 0x82, 0x81, b'w'];
+        // segment of unknown size
+        data.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0x01, 0xff, 0xff, 0xff]);
+        data.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        // info: duration 2500.0 as float, default timecode scale
+        data.extend_from_slice(&[0x15, 0x49, 0xa9, 0x66, 0x87, 0x44, 0x89, 0x84]);
+        data.extend_from_slice(&2500f32.to_be_bytes());
+        // tracks: one video entry of 640 x 360
+        data.extend_from_slice(&[0x16, 0x54, 0xae, 0x6b, 0x8f, 0xae, 0x8d, 0x83, 0x81, 0x01]);
+        data.extend_from_slice(&[0xe0, 0x88, 0xb0, 0x82, 0x02, 0x80, 0xba, 0x82, 0x01, 0x68]);
+        data.extend_from_slice(&[0x1f, 0x43, 0xb6, 0x75, 0x01, 0xff, 0xff, 0xff]);
+        let webm = read_webm(&mut Cursor::new(data)).unwrap();
+        assert_eq!(webm.duration, 2.5);
+        assert_eq!((webm.width, webm.height), (640, 360));
+    }
+}
@@ -0,0 +1,171 @@
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use crate::config;
+
+/// A bounded cache, shared by all workers. Entries carry a version (e.g. an
+/// ETag or a file's size and mtime) and are only returned for the same
+/// version, so changed files are read again. When full, the least recently
+/// used entries are dropped.
+pub struct Cache<K, V> {
+    max_entries: usize,
+    max_bytes: usize,
+    inner: Mutex<Inner<K, V>>,
+}
+
+struct Inner<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    bytes: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+struct Entry<V> {
+    version: String,
+    value: V,
+    size: usize,
+    used: u64,
+}
+
+/// Counters to choose the cache limits.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Stats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Cache<K, V> {
+    pub fn new(limits: &config::Cache) -> Cache<K, V> {
+        Cache::with_limits(limits.entries, limits.megabytes * 1024 * 1024)
+    }
+
+    pub fn with_limits(max_entries: usize, max_bytes: usize) -> Cache<K, V> {
+        Cache {
+            max_entries,
+            max_bytes,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                bytes: 0,
+                clock: 0,
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    pub fn get(&self, key: &K, version: &str) -> Option<V> {
+        let mut inner = self.lock();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let found = match inner.entries.get_mut(key) {
+            Some(entry) if entry.version == version => {
+                entry.used = clock;
+                Some(entry.value.clone())
+            }
+            _ => None,
+        };
+        match found {
+            Some(_) => inner.hits += 1,
+            None => inner.misses += 1,
+        }
+        found
+    }
+
+    /// Adds or replaces an entry. `size` is the approximate memory used by
+    /// the value in bytes, values larger than the cache are not stored.
+    pub fn insert(&self, key: K, version: String, value: V, size: usize) {
+        if self.max_entries == 0 || size > self.max_bytes {
+            return;
+        }
+        let mut inner = self.lock();
+        inner.clock += 1;
+        let entry = Entry {
+            version,
+            value,
+            size,
+            used: inner.clock,
+        };
+        if let Some(replaced) = inner.entries.insert(key, entry) {
+            inner.bytes -= replaced.size;
+        }
+        inner.bytes += size;
+        while inner.entries.len() > self.max_entries || inner.bytes > self.max_bytes {
+            inner.evict();
+        }
+    }
+
+    pub fn remove(&self, key: &K) {
+        let mut inner = self.lock();
+        if let Some(entry) = inner.entries.remove(key) {
+            inner.bytes -= entry.size;
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let inner = self.lock();
+        Stats {
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+            hits: inner.hits,
+            misses: inner.misses,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner<K, V>> {
+        // a panic while holding the lock leaves consistent entries behind
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> Inner<K, V> {
+    /// Removes the least recently used entry. A linear scan is fast enough
+    /// for the few thousand entries a cache holds.
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.size;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{Cache, Stats};
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache: Cache<&str, u32> = Cache::with_limits(2, 100);
+        cache.insert("a", "1".to_owned(), 1, 10);
+        cache.insert("b", "1".to_owned(), 2, 10);
+        assert_eq!(cache.get(&"a", "1"), Some(1));
+        cache.insert("c", "1".to_owned(), 3, 10);
+        assert_eq!(cache.get(&"b", "1"), None);
+        assert_eq!(cache.get(&"a", "1"), Some(1));
+        // changed version
+        assert_eq!(cache.get(&"c", "2"), None);
+        // too large for the cache, then evicting by size
+        cache.insert("d", "1".to_owned(), 4, 101);
+        cache.insert("e", "1".to_owned(), 5, 95);
+        assert_eq!(
+            cache.stats(),
+            Stats {
+                entries: 1,
+                bytes: 95,
+                hits: 2,
+                misses: 2
+            }
+        );
+    }
+}
@@ -1,5 +1,10 @@
+use crate::error::Error;
+use percent_encoding::percent_decode_str;
 use serde::Deserialize;
 use serde_yaml;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -7,6 +12,18 @@ use std::path::PathBuf;
 pub struct Config {
     pub serving: Serving,
     pub urls: Urls,
+    #[serde(default)]
+    pub images: Images,
+    #[serde(default)]
+    pub mapping: Mapping,
+    #[serde(default)]
+    pub cache: Cache,
+    #[serde(default)]
+    pub watch: Watch,
+    #[serde(default)]
+    pub cors: Cors,
+    #[serde(default)]
+    pub proxy: Proxy,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
@@ -14,6 +31,19 @@ pub struct Serving {
     pub path: PathBuf,
     pub host: String,
     pub port: u32,
+    /// Files parsed at the same time, by all scans together, 0 for the
+    /// number of CPUs. One scan uses at most half of them.
+    #[serde(rename = "scan threads", default)]
+    pub scan_threads: usize,
+    /// Seconds a manifest or collection may take before the request fails
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+}
+
+fn default_timeout() -> u64 {
+    30
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
@@ -24,6 +54,236 @@ pub struct Urls {
     pub image_api: String,
     #[serde(rename = "presentation api")]
     pub presentation_api: String,
+    /// Image id of a page in a multi-page file, `{file}` is replaced with
+    /// the file name, `{page}` with the page number starting at 1 and
+    /// `{index}` with the page number starting at 0.
+    #[serde(rename = "page id", default = "default_page_id")]
+    pub page_id: String,
+}
+
+fn default_page_id() -> String {
+    "{file};{page}".to_owned()
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Images {
+    #[serde(default)]
+    pub integrity: IntegrityMode,
+    /// Read and checksum image data, too (reads whole files)
+    #[serde(rename = "verify image data", default)]
+    pub verify_image_data: bool,
+    /// Look for text chunks after the image data if integrity checks are off
+    #[serde(rename = "text after data", default)]
+    pub text_after_data: bool,
+    #[serde(default)]
+    pub orientation: OrientationMode,
+    /// File keeping what was read from images across restarts
+    #[serde(default)]
+    pub index: Option<PathBuf>,
+}
+
+/// What to do with images that fail integrity checks (bad checksums,
+/// truncated files or data after the end marker).
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegrityMode {
+    /// Skip all checks and stop reading at the image data
+    Off,
+    /// Report damaged images, but still include them in manifests
+    #[default]
+    Lenient,
+    /// Report damaged images and leave them out of manifests
+    Strict,
+}
+
+/// How images with an orientation tag (EXIF, TIFF, HEIF or JPEG XL) end
+/// up in manifests.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrientationMode {
+    /// Use the stored size and ignore the orientation
+    Ignore,
+    /// The image server delivers oriented images, so canvas and image
+    /// use the displayed size
+    #[default]
+    Apply,
+    /// The image server delivers stored images, so the canvas uses the
+    /// displayed size and the painting annotation rotates the image with
+    /// an Image API selector
+    Selector,
+}
+
+/// Which symbolic links below `serving.path` are served.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Only links to files and directories inside `serving.path`
+    #[default]
+    Inside,
+    /// All links, e.g. to mounted storage elsewhere
+    Follow,
+}
+
+/// Limits of the in-memory caches for generated documents (manifests and
+/// collections) and for the images read from files. Each cache holds up to
+/// `entries` entries and about `megabytes` of data, 0 entries disable it.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Cache {
+    #[serde(default = "default_cache_entries")]
+    pub entries: usize,
+    #[serde(default = "default_cache_megabytes")]
+    pub megabytes: usize,
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache {
+            entries: default_cache_entries(),
+            megabytes: default_cache_megabytes(),
+        }
+    }
+}
+
+fn default_cache_entries() -> usize {
+    1000
+}
+
+fn default_cache_megabytes() -> usize {
+    64
+}
+
+/// Looks for changed directories below `serving.path`, drops their cached
+/// documents and with `prewarm` generates them again right away. Polling
+/// looks every `interval` seconds.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Watch {
+    #[serde(default)]
+    pub mode: WatchMode,
+    #[serde(default = "default_watch_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub prewarm: bool,
+}
+
+impl Default for Watch {
+    fn default() -> Watch {
+        Watch {
+            mode: WatchMode::default(),
+            interval: default_watch_interval(),
+            prewarm: false,
+        }
+    }
+}
+
+fn default_watch_interval() -> u64 {
+    60
+}
+
+/// How changes below `serving.path` are noticed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchMode {
+    #[default]
+    Off,
+    /// Change notifications of the kernel (inotify), polling where they are
+    /// not available
+    Notify,
+    /// Scan all directories every `interval` seconds, e.g. on network file
+    /// systems, where notifications miss changes made by other hosts
+    Poll,
+}
+
+/// Origins allowed to load documents and files in browsers, "*" for any.
+/// No origins turn CORS off. Preflight results are cached for `max age`
+/// seconds.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Cors {
+    #[serde(default = "default_cors_origins")]
+    pub origins: Vec<String>,
+    #[serde(rename = "max age", default = "default_cors_max_age")]
+    pub max_age: u64,
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors {
+            origins: default_cors_origins(),
+            max_age: default_cors_max_age(),
+        }
+    }
+}
+
+// IIIF resources are meant to be shared
+fn default_cors_origins() -> Vec<String> {
+    vec!["*".to_owned()]
+}
+
+fn default_cors_max_age() -> u64 {
+    86400
+}
+
+/// Reverse proxies whose `Forwarded` and `X-Forwarded-*` headers are used
+/// for the URLs in documents, so one instance can serve internal and
+/// public hosts. Without trusted proxies, the configured URLs are used.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Proxy {
+    /// Addresses of the proxies
+    #[serde(default)]
+    pub trusted: Vec<IpAddr>,
+    /// Take scheme and host of `urls.image api` from the headers, too, for
+    /// image servers behind the same proxy
+    #[serde(rename = "image api", default)]
+    pub image_api: bool,
+}
+
+/// Where labels embedded in image files end up in the manifest. Each
+/// property lists keys in order of preference, the first one found is used.
+/// Keys not routed to a property are shown as metadata.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Mapping {
+    #[serde(default)]
+    pub label: Vec<String>,
+    #[serde(default)]
+    pub summary: Vec<String>,
+    #[serde(rename = "required statement", default)]
+    pub required_statement: Vec<String>,
+    /// Only used if the value is a URL, e.g. a Creative Commons license
+    #[serde(default)]
+    pub rights: Vec<String>,
+    /// Only used if the value is a date
+    #[serde(rename = "nav date", default)]
+    pub nav_date: Vec<String>,
+    /// Keys left out of the manifest
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// New names for keys shown as metadata
+    #[serde(default)]
+    pub rename: BTreeMap<String, String>,
+}
+
+impl Mapping {
+    /// The mapping of a directory: properties set in `other` (from the
+    /// sidecar) replace the ones set here, renames are added.
+    pub fn merge(&self, other: &Mapping) -> Mapping {
+        let pick = |this: &Vec<String>, that: &Vec<String>| {
+            if that.is_empty() {
+                this.clone()
+            } else {
+                that.clone()
+            }
+        };
+        let mut rename = self.rename.clone();
+        rename.extend(other.rename.clone());
+        Mapping {
+            label: pick(&self.label, &other.label),
+            summary: pick(&self.summary, &other.summary),
+            required_statement: pick(&self.required_statement, &other.required_statement),
+            rights: pick(&self.rights, &other.rights),
+            nav_date: pick(&self.nav_date, &other.nav_date),
+            ignore: pick(&self.ignore, &other.ignore),
+            rename,
+        }
+    }
 }
 
 impl Config {
@@ -32,6 +292,109 @@ impl Config {
         let config: Config = serde_yaml::from_reader(f)?;
         Ok(config)
     }
+
+    /// Existing directory of an id below `serving.path`. Ids are directory
+    /// names joined with `urls.path sep` (or `/`). Ids with names that are
+    /// not plain names (`..`, absolute paths, ...) or that lead outside
+    /// `serving.path` are rejected.
+    pub fn directory(&self, id: &str) -> crate::error::Result<PathBuf> {
+        let names = match self.urls.names(id) {
+            Some(names) => names,
+            None => return Err(Error::BadIdentifier(id.to_owned())),
+        };
+        let mut directory = self.serving.path.clone();
+        for name in names {
+            let mut components = Path::new(&name).components();
+            let plain = matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            );
+            if !plain || name.contains(['\\', '\0']) {
+                return Err(Error::BadIdentifier(id.to_owned()));
+            }
+            directory.push(name);
+        }
+        if !directory.is_dir() {
+            return Err(Error::NotFound(id.to_owned()));
+        }
+        if !self.is_served(&directory) {
+            println!(
+                "Not serving {}: outside of serving.path",
+                directory.display()
+            );
+            return Err(Error::BadIdentifier(id.to_owned()));
+        }
+        Ok(directory)
+    }
+
+    /// False if a path resolves to a place outside of `serving.path`
+    /// through symbolic links, unless `serving.symlinks` is `follow`.
+    pub fn is_served(&self, path: &Path) -> bool {
+        if self.serving.symlinks == SymlinkPolicy::Follow {
+            return true;
+        }
+        match (path.canonicalize(), self.serving.path.canonicalize()) {
+            (Ok(path), Ok(root)) => path.starts_with(root),
+            _ => false,
+        }
+    }
+}
+
+impl Urls {
+    /// Id of a directory from the names of the directories leading to it.
+    pub fn id<S: AsRef<str>>(&self, names: &[S]) -> String {
+        names.iter().fold(String::new(), |parent, name| {
+            self.join(&parent, &self.escape(name.as_ref()))
+        })
+    }
+
+    /// Names of the directories in an id, None if it is not valid UTF-8
+    /// after decoding. Besides `path sep`, `/` separates names, too.
+    pub fn names(&self, id: &str) -> Option<Vec<String>> {
+        if id.is_empty() {
+            return Some(Vec::new());
+        }
+        id.split(self.path_sep.as_str())
+            .flat_map(|part| part.split('/'))
+            .map(|name| {
+                let decoded = percent_decode_str(name).decode_utf8().ok()?;
+                Some(decoded.into_owned())
+            })
+            .collect()
+    }
+
+    /// Id of a child, e.g. a file, with an id part that is already escaped.
+    pub fn join(&self, parent: &str, child: &str) -> String {
+        if parent.is_empty() {
+            child.to_owned()
+        } else {
+            format!("{}{}{}", parent, self.path_sep, child)
+        }
+    }
+
+    /// A name as part of an id: `%` and the characters of `path sep` are
+    /// percent-encoded, so `with-meta` and `with/meta` get different ids.
+    pub fn escape(&self, name: &str) -> String {
+        let mut escaped = String::with_capacity(name.len());
+        for c in name.chars() {
+            if c == '%' || self.path_sep.contains(c) {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    escaped.push_str(&format!("%{:02X}", byte));
+                }
+            } else {
+                escaped.push(c);
+            }
+        }
+        escaped
+    }
+
+    pub fn page_id(&self, file: &str, index: u32) -> String {
+        self.page_id
+            .replace("{file}", file)
+            .replace("{page}", &(index + 1).to_string())
+            .replace("{index}", &index.to_string())
+    }
 }
 
 impl Serving {
@@ -43,8 +406,13 @@ impl Serving {
 #[cfg(test)]
 mod tests {
 
-    use crate::config::Config;
+    use crate::config::{
+        Config, IntegrityMode, Mapping, OrientationMode, SymlinkPolicy, WatchMode,
+    };
+    use crate::error::Error;
     use serde_yaml;
+    use std::net::IpAddr;
+    use std::path::PathBuf;
 
     const FULL_CONFIG: &str = "
     # Which directory shall be served where (host and port)?
@@ -52,6 +420,8 @@ mod tests {
         path: samples
         host: localhost
         port: 7890
+        scan threads: 8
+        timeout: 10
 
     # The urls part is important for the public facing user interaction
     # and will end up in the generated JSON.
@@ -59,6 +429,45 @@ mod tests {
         path sep: '-'
         image api: http://localhost:1234/iiif/image/v2
         presentation api: http://localhost:1234/iiif/presentation/v2
+
+    images:
+        integrity: strict
+        orientation: selector
+        index: /var/cache/forager/index.jsonl
+
+    mapping:
+        label: [Title]
+        required statement: [Copyright]
+        ignore: [Software]
+        rename:
+            Author: Creator
+
+    cache:
+        entries: 50
+
+    watch:
+        mode: poll
+        interval: 10
+        prewarm: true
+
+    cors:
+        origins: ['https://viewer.example']
+        max age: 600
+
+    proxy:
+        trusted: [127.0.0.1, '::1']
+        image api: true
+    ";
+
+    const MINIMAL_CONFIG: &str = "
+    serving:
+        path: samples
+        host: localhost
+        port: 7890
+    urls:
+        path sep: '-'
+        image api: http://localhost:1234/iiif/image/v2
+        presentation api: http://localhost:1234/iiif/presentation/v2
     ";
 
     #[test]
@@ -66,17 +475,51 @@ mod tests {
         let config: Config = serde_yaml::from_str(FULL_CONFIG).unwrap();
         assert_eq!(config.serving.host, "localhost");
         assert_eq!(config.serving.port, 7890);
+        assert_eq!(config.serving.scan_threads, 8);
+        assert_eq!(config.serving.timeout, 10);
         assert_eq!(config.urls.path_sep, "-");
         assert_eq!(config.urls.image_api, "http://localhost:1234/iiif/image/v2");
         assert_eq!(
             config.urls.presentation_api,
             "http://localhost:1234/iiif/presentation/v2"
         );
+        assert_eq!(config.images.integrity, IntegrityMode::Strict);
+        assert_eq!(config.images.orientation, OrientationMode::Selector);
+        assert_eq!(
+            config.images.index,
+            Some(PathBuf::from("/var/cache/forager/index.jsonl"))
+        );
+        assert_eq!(config.mapping.label, vec!["Title".to_owned()]);
+        assert_eq!(config.mapping.ignore, vec!["Software".to_owned()]);
+        assert_eq!(config.mapping.rename["Author"], "Creator");
+        assert_eq!(config.cache.entries, 50);
+        assert_eq!(config.cache.megabytes, 64);
+        assert_eq!(config.watch.mode, WatchMode::Poll);
+        assert_eq!(config.watch.interval, 10);
+        assert!(config.watch.prewarm);
+        assert_eq!(config.cors.origins, vec!["https://viewer.example"]);
+        assert_eq!(config.cors.max_age, 600);
+        assert_eq!(
+            config.proxy.trusted,
+            vec![
+                IpAddr::from([127, 0, 0, 1]),
+                "::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert!(config.proxy.image_api);
+
+        let sidecar = Mapping {
+            label: vec!["Headline".to_owned()],
+            ..Mapping::default()
+        };
+        let merged = config.mapping.merge(&sidecar);
+        assert_eq!(merged.label, vec!["Headline".to_owned()]);
+        assert_eq!(merged.required_statement, vec!["Copyright".to_owned()]);
     }
 
     #[test]
     fn load_minimal() {
-        let config: Config = serde_yaml::from_str(FULL_CONFIG).unwrap();
+        let config: Config = serde_yaml::from_str(MINIMAL_CONFIG).unwrap();
         assert_eq!(config.serving.host, "localhost");
         assert_eq!(config.serving.port, 7890);
         assert_eq!(config.urls.path_sep, "-");
@@ -85,5 +528,55 @@ mod tests {
             config.urls.presentation_api,
             "http://localhost:1234/iiif/presentation/v2"
         );
+        assert_eq!(config.images.integrity, IntegrityMode::Lenient);
+        assert_eq!(config.images.orientation, OrientationMode::Apply);
+        assert_eq!(config.images.index, None);
+        assert_eq!(config.mapping, Mapping::default());
+        assert_eq!(config.serving.scan_threads, 0);
+        assert_eq!(config.serving.timeout, 30);
+        assert_eq!(config.watch.mode, WatchMode::Off);
+        assert_eq!(config.watch.interval, 60);
+        assert_eq!(config.cors.origins, vec!["*"]);
+        assert!(config.proxy.trusted.is_empty());
+        assert_eq!(config.urls.page_id("scan.tif", 1), "scan.tif;2");
+        let urls = &config.urls;
+        for names in &[
+            vec!["with-meta"],
+            vec!["with", "meta"],
+            vec!["100%", "a b", "ü"],
+        ] {
+            let id = urls.id(names);
+            assert_eq!(urls.names(&id).unwrap(), *names, "{}", id);
+        }
+        assert_eq!(urls.id(&["with-meta", "x"]), "with%2Dmeta-x");
+        assert_eq!(urls.names("a/b-c").unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(urls.names("%FF"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn confines_directories() {
+        let root = std::env::temp_dir().join(format!("forager-config-{}", std::process::id()));
+        let outside = root.join("outside");
+        std::fs::create_dir_all(root.join("served/a/b")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("served/a/link")).unwrap();
+        let mut config: Config = serde_yaml::from_str(MINIMAL_CONFIG).unwrap();
+        config.serving.path = root.join("served");
+
+        let served = config.directory("a-b").unwrap();
+        let not_found = config.directory("a-c");
+        let bad: Vec<_> = ["..", "a-..-b", "a--b", "a/../b", "a\\b", "/etc", "a-link"]
+            .iter()
+            .filter(|id| !matches!(config.directory(id), Err(Error::BadIdentifier(_))))
+            .collect();
+        config.serving.symlinks = SymlinkPolicy::Follow;
+        let followed = config.directory("a-link");
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(served, root.join("served/a/b"));
+        assert!(matches!(not_found, Err(Error::NotFound(_))));
+        assert!(bad.is_empty(), "{:?}", bad);
+        assert!(followed.is_ok());
     }
 }
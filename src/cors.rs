@@ -0,0 +1,136 @@
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::HttpResponse;
+
+use crate::config;
+
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+/// Request headers viewers send: content negotiation, conditional and range
+/// requests
+const ALLOWED_HEADERS: &str = "Accept, If-None-Match, If-Modified-Since, Range";
+/// Response headers scripts may read besides the safelisted ones
+const EXPOSED_HEADERS: &str = "ETag, Content-Range, Accept-Ranges, Retry-After";
+
+/// Cross-origin resource sharing, so viewers on other sites can load
+/// manifests, collections and media files.
+#[derive(Clone)]
+pub struct Cors {
+    origins: Vec<String>,
+    max_age: u64,
+}
+
+impl Cors {
+    pub fn new(config: &config::Cors) -> Cors {
+        Cors {
+            origins: config.origins.clone(),
+            max_age: config.max_age,
+        }
+    }
+
+    /// Answers an `OPTIONS` request, with the preflight headers if the
+    /// origin is allowed.
+    pub fn preflight(&self, request: &HeaderMap) -> HttpResponse {
+        let mut response = HttpResponse::NoContent();
+        response.header(header::ALLOW, ALLOWED_METHODS);
+        if request.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD) {
+            if let Some(origin) = self.allowed_origin(request) {
+                response
+                    .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+                    .header(header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS)
+                    .header(header::ACCESS_CONTROL_ALLOW_HEADERS, ALLOWED_HEADERS)
+                    .header(header::ACCESS_CONTROL_MAX_AGE, self.max_age.to_string());
+            }
+        }
+        let mut response = response.finish();
+        self.vary(response.headers_mut());
+        response
+    }
+
+    /// Adds the headers for the actual request to its response.
+    pub fn add_headers(&self, request: &HeaderMap, response: &mut HeaderMap) {
+        if let Some(origin) = self.allowed_origin(request) {
+            response.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            response.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static(EXPOSED_HEADERS),
+            );
+        }
+        self.vary(response);
+    }
+
+    /// `*` if all origins are allowed, the origin of the request if it is
+    /// listed, None otherwise.
+    fn allowed_origin(&self, request: &HeaderMap) -> Option<HeaderValue> {
+        if self.origins.iter().any(|origin| origin == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let origin = request.get(header::ORIGIN)?;
+        let listed = self
+            .origins
+            .iter()
+            .any(|allowed| allowed.as_bytes() == origin.as_bytes());
+        if listed {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Responses depend on the origin unless all origins are allowed.
+    fn vary(&self, response: &mut HeaderMap) {
+        if !self.origins.is_empty() && !self.origins.iter().any(|origin| origin == "*") {
+            response.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config;
+    use crate::cors::Cors;
+    use actix_web::http::header::{self, HeaderMap, HeaderValue};
+
+    fn cors(origins: &[&str]) -> Cors {
+        Cors::new(&config::Cors {
+            origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            max_age: 600,
+        })
+    }
+
+    fn request(origin: &'static str) -> HeaderMap {
+        let mut request = HeaderMap::new();
+        request.insert(header::ORIGIN, HeaderValue::from_static(origin));
+        request.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("GET"),
+        );
+        request
+    }
+
+    #[test]
+    fn allows_listed_origins() {
+        let mut response = HeaderMap::new();
+        cors(&["*"]).add_headers(&request("https://viewer.example"), &mut response);
+        assert_eq!(
+            response.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "*"
+        );
+        assert!(response.get(header::VARY).is_none());
+
+        let listed = cors(&["https://viewer.example"]);
+        let preflight = listed.preflight(&request("https://viewer.example"));
+        let headers = preflight.headers();
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://viewer.example"
+        );
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        assert_eq!(headers.get(header::VARY).unwrap(), "Origin");
+
+        let mut response = HeaderMap::new();
+        listed.add_headers(&request("https://other.example"), &mut response);
+        assert!(response.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        let mut response = HeaderMap::new();
+        cors(&[]).add_headers(&request("https://viewer.example"), &mut response);
+        assert!(response.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+
+use std::fmt;
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Why a manifest, collection or file could not be served.
+#[derive(Debug)]
+pub enum Error {
+    /// No directory or file for the id
+    NotFound(String),
+    /// An id that cannot name a directory below `serving.path`
+    BadIdentifier(String),
+    /// A file that exists but could not be read
+    UnreadableFile(PathBuf, std::io::Error),
+    /// A meta.json or meta.yml that could not be parsed
+    InvalidSidecar(PathBuf, String),
+    Io(std::io::Error),
+    /// Scanning took longer than `serving.timeout`
+    Timeout,
+    /// The `Accept` header allows neither JSON-LD nor JSON
+    NotAcceptable,
+}
+
+/// Body of error responses after RFC 7807, `application/problem+json`
+#[derive(Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+}
+
+impl Error {
+    /// Explanation for clients, without paths on the server.
+    fn detail(&self) -> String {
+        match self {
+            Error::NotFound(id) => format!("nothing found for {}", id),
+            Error::BadIdentifier(id) => format!("{} is not a valid identifier", id),
+            Error::UnreadableFile(path, _) => match path.file_name() {
+                Some(name) => format!("{} could not be read", name.to_string_lossy()),
+                None => "a file could not be read".to_owned(),
+            },
+            Error::InvalidSidecar(path, _) => match path.file_name() {
+                Some(name) => format!(
+                    "the {} of this directory is invalid",
+                    name.to_string_lossy()
+                ),
+                None => "the metadata of this directory is invalid".to_owned(),
+            },
+            Error::Io(_) => "reading the directory failed".to_owned(),
+            Error::Timeout => "scanning the directory takes too long, please try again".to_owned(),
+            Error::NotAcceptable => {
+                "documents are available as application/ld+json with the IIIF Presentation 3 \
+                 profile or application/json"
+                    .to_owned()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(id) => write!(f, "Not found: {}", id),
+            Error::BadIdentifier(id) => write!(f, "Bad identifier: {}", id),
+            Error::UnreadableFile(path, e) => write!(f, "Could not read {}: {}", path.display(), e),
+            Error::InvalidSidecar(path, e) => {
+                write!(f, "Invalid sidecar {}: {}", path.display(), e)
+            }
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Timeout => write!(f, "Timeout"),
+            Error::NotAcceptable => write!(f, "Not acceptable"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::BadIdentifier(_) => StatusCode::BAD_REQUEST,
+            Error::UnreadableFile(_, _) | Error::InvalidSidecar(_, _) | Error::Io(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Error::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+            Error::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            println!("{}", self);
+        }
+        let mut response = HttpResponseBuilder::new(status);
+        if let Error::Timeout = self {
+            response.header(header::RETRY_AFTER, "10");
+        }
+        let problem = Problem {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: self.detail(),
+        };
+        response
+            .content_type("application/problem+json")
+            .body(serde_json::to_string(&problem).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use std::path::PathBuf;
+
+    #[test]
+    fn maps_to_status_codes() {
+        assert_eq!(
+            Error::NotFound("a-b".to_owned()).status_code(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            Error::BadIdentifier("..".to_owned()).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        let sidecar = Error::InvalidSidecar(PathBuf::from("/srv/a/meta.yml"), "line 2".to_owned());
+        assert_eq!(sidecar.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        // no server paths in responses
+        assert_eq!(
+            sidecar.detail(),
+            "the meta.yml of this directory is invalid"
+        );
+        assert_eq!(
+            Error::NotAcceptable.status_code(),
+            StatusCode::NOT_ACCEPTABLE
+        );
+        let response = Error::Timeout.error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get("retry-after").unwrap(), "10");
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/problem+json"
+        );
+    }
+}
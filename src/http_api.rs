@@ -1,23 +1,88 @@
-use actix_web::{web, App, HttpResponse, HttpServer};
+use actix_web::dev::{Body, Service, SizedStream};
+use actix_web::error::BlockingError;
+use actix_web::http::{header, Method};
+use actix_web::web::Bytes;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use futures::future::{self, Either};
+use futures::Stream;
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
 
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::cache::{Cache, Stats};
+use crate::config::Urls;
+use crate::cors::Cors;
+use crate::error::{Error, Result};
 use crate::iiif::IiifGenerator;
 use crate::image::source::ImageSource;
+use crate::image::Format;
+use crate::proxy::Proxy;
+use crate::validator::Validator;
+use crate::watch::Watcher;
+
+const CHUNK_LENGTH: u64 = 64 * 1024;
+
+const IIIF_PROFILE: &str = "http://iiif.io/api/presentation/3/context.json";
+/// Media type of IIIF Presentation 3 documents, with IIIF_PROFILE
+const IIIF_MEDIA_TYPE: &str =
+    "application/ld+json;profile=\"http://iiif.io/api/presentation/3/context.json\"";
+
+/// Generated manifests and collections as JSON, by url path and forwarded
+/// URLs, with the ETag as version.
+pub type Documents = Cache<String, Bytes>;
 
 #[actix_rt::main]
 pub async fn start(
     iiif_generator: IiifGenerator,
     image_source: ImageSource,
+    documents: Documents,
+    watcher: Option<Watcher>,
+    cors: Cors,
+    proxy: Proxy,
     bind: String,
 ) -> std::io::Result<()> {
     println!("Starting iiif-presenter on http://{}", bind);
     let iiif_generator_ref = web::Data::new(iiif_generator);
     let image_source_ref = web::Data::new(image_source);
+    let documents_ref = web::Data::new(documents);
+    let proxy_ref = web::Data::new(proxy);
+    if let Some(watcher) = watcher {
+        watcher.spawn(
+            image_source_ref.clone(),
+            iiif_generator_ref.clone(),
+            documents_ref.clone(),
+            proxy_ref.clone(),
+        );
+    }
     HttpServer::new(move || {
+        let cors = cors.clone();
         App::new()
+            .wrap_fn(move |request, service| {
+                if request.method() == Method::OPTIONS {
+                    let response = cors.preflight(request.headers());
+                    return Either::Left(future::ok(request.into_response(response)));
+                }
+                let request_headers = request.headers().clone();
+                let response = service.call(request);
+                let cors = cors.clone();
+                Either::Right(async move {
+                    let mut response = response.await?;
+                    cors.add_headers(&request_headers, response.headers_mut());
+                    Ok(response)
+                })
+            })
             .app_data(iiif_generator_ref.clone())
             .app_data(image_source_ref.clone())
+            .app_data(documents_ref.clone())
+            .app_data(proxy_ref.clone())
             .service(index)
             .service(collection)
+            .service(media_file)
+            .service(cache_stats)
     })
     .bind(bind)?
     .run()
@@ -28,30 +93,471 @@ pub async fn start(
 async fn index(
     image_source: web::Data<ImageSource>,
     iiif_generator: web::Data<IiifGenerator>,
-    path: web::Path<String>,
-) -> HttpResponse {
-    println!("Url-Path (Manifest): {}", path.to_string());
-    let id = path.to_string();
-    let images = match image_source.load(&id) {
-        Some(images) => images,
-        None => return HttpResponse::NotFound().body(id),
+    documents: web::Data<Documents>,
+    proxy: web::Data<Proxy>,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let id = decode(request_path(&request, "/manifest"))?;
+    println!("Url-Path (Manifest): {}", id);
+    let media_type = document_media_type(&request).ok_or(Error::NotAcceptable)?;
+    let deadline = Instant::now() + image_source.timeout();
+    let forwarded = proxy.urls(&request);
+    let validator = {
+        let (image_source, sub_path) = (image_source.clone(), id.clone());
+        blocking(deadline, move || image_source.manifest_validator(&sub_path)).await?
     };
-    println!("Images: {}", images.len());
-    match iiif_generator.get_ref().manifest_for(&id, images) {
-        Ok(manifest) => HttpResponse::Ok().json(manifest),
-        Err(e) => HttpResponse::InternalServerError().body(e),
+    let validator = variant(validator, forwarded.as_ref());
+    let representation = representation(&validator, media_type);
+    if representation.is_fresh(&request) {
+        return Ok(representation.not_modified());
+    }
+    let key = document_key(&id, "manifest", forwarded.as_ref());
+    if let Some(json) = documents.get(&key, &validator.tag) {
+        return Ok(json_response(&representation, media_type, json));
     }
+    let json = blocking(deadline, move || {
+        let urls = forwarded.unwrap_or_else(|| iiif_generator.urls().clone());
+        manifest_json(&image_source, &iiif_generator, &id, &urls)
+    })
+    .await?;
+    documents.insert(key, validator.tag, json.clone(), json.len());
+    Ok(json_response(&representation, media_type, json))
 }
 
 #[get("/{id:.*}/collection")]
 async fn collection(
+    image_source: web::Data<ImageSource>,
     iiif_generator: web::Data<IiifGenerator>,
-    path: web::Path<String>,
+    documents: web::Data<Documents>,
+    proxy: web::Data<Proxy>,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let id = decode(request_path(&request, "/collection"))?;
+    println!("Url-Path (Collection): {}", id);
+    let media_type = document_media_type(&request).ok_or(Error::NotAcceptable)?;
+    let deadline = Instant::now() + image_source.timeout();
+    let forwarded = proxy.urls(&request);
+    let validator = {
+        let (image_source, sub_path) = (image_source.clone(), id.clone());
+        blocking(deadline, move || {
+            image_source.collection_validator(&sub_path)
+        })
+        .await?
+    };
+    let validator = variant(validator, forwarded.as_ref());
+    let representation = representation(&validator, media_type);
+    if representation.is_fresh(&request) {
+        return Ok(representation.not_modified());
+    }
+    let key = document_key(&id, "collection", forwarded.as_ref());
+    if let Some(json) = documents.get(&key, &validator.tag) {
+        return Ok(json_response(&representation, media_type, json));
+    }
+    let json = blocking(deadline, move || {
+        let urls = forwarded.unwrap_or_else(|| iiif_generator.urls().clone());
+        collection_json(&iiif_generator, &id, &urls)
+    })
+    .await?;
+    documents.insert(key, validator.tag, json.clone(), json.len());
+    Ok(json_response(&representation, media_type, json))
+}
+
+/// The request path without the leading `/` and `suffix`, still encoded.
+/// Ids are taken from here instead of the route, which decodes most but
+/// not all characters, so `%` in names could not be told apart.
+fn request_path<'r>(request: &'r HttpRequest, suffix: &str) -> &'r str {
+    let path = request.uri().path();
+    let path = path.strip_prefix('/').unwrap_or(path);
+    path.strip_suffix(suffix).unwrap_or(path)
+}
+
+fn decode(encoded: &str) -> Result<String> {
+    match percent_decode_str(encoded).decode_utf8() {
+        Ok(decoded) => Ok(decoded.into_owned()),
+        Err(_) => Err(Error::BadIdentifier(encoded.to_owned())),
+    }
+}
+
+/// Runs file system work on the thread pool for blocking tasks, so a large
+/// directory does not hold up other requests on the same worker. After the
+/// deadline the request fails, but the work goes on and fills the image
+/// cache for the next attempt.
+async fn blocking<T, F>(deadline: Instant, work: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    match actix_rt::time::timeout(remaining, web::block(work)).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(BlockingError::Error(e))) => Err(e),
+        Ok(Err(BlockingError::Canceled)) => {
+            Err(Error::Io(std::io::Error::other("scanning was canceled")))
+        }
+        Err(_elapsed) => Err(Error::Timeout),
+    }
+}
+
+/// Documents with forwarded URLs get their own ETag.
+pub fn variant(validator: Validator, forwarded: Option<&Urls>) -> Validator {
+    match forwarded {
+        Some(urls) => validator.vary(&format!("{} {}", urls.presentation_api, urls.image_api)),
+        None => validator,
+    }
+}
+
+/// The validator of the response in `media_type`. Plain JSON gets its own
+/// ETag, as the same one must not name two representations. JSON-LD keeps
+/// the ETag the cache uses.
+fn representation(validator: &Validator, media_type: &str) -> Validator {
+    if media_type == IIIF_MEDIA_TYPE {
+        validator.clone()
+    } else {
+        validator.vary(media_type)
+    }
+}
+
+/// Key of a generated document in the cache, e.g. `a-b/manifest`, followed
+/// by the forwarded URLs its ids were made with.
+pub fn document_key(id: &str, kind: &str, forwarded: Option<&Urls>) -> String {
+    match forwarded {
+        Some(urls) => format!(
+            "{}/{} {} {}",
+            id, kind, urls.presentation_api, urls.image_api
+        ),
+        None => format!("{}/{}", id, kind),
+    }
+}
+
+/// Generates the manifest of a directory as JSON.
+pub fn manifest_json(
+    image_source: &ImageSource,
+    iiif_generator: &IiifGenerator,
+    id: &str,
+    urls: &Urls,
+) -> Result<Bytes> {
+    let images = image_source.load(id)?;
+    println!("Images: {}", images.len());
+    let manifest = iiif_generator.manifest_for(id, images, urls)?;
+    Ok(to_json(&manifest))
+}
+
+/// Generates the collection of a directory as JSON.
+pub fn collection_json(iiif_generator: &IiifGenerator, id: &str, urls: &Urls) -> Result<Bytes> {
+    let document = iiif_generator.collection_for(id, urls)?;
+    Ok(to_json(&document))
+}
+
+fn to_json<T: Serialize>(document: &T) -> Bytes {
+    // documents only hold strings, numbers and maps with string keys
+    Bytes::from(serde_json::to_vec(document).expect("documents serialize to JSON"))
+}
+
+#[derive(Serialize)]
+struct CacheStats {
+    documents: Stats,
+    images: Stats,
+}
+
+/// Hits, misses and sizes of the caches, to choose their limits.
+#[get("/cache")]
+async fn cache_stats(
+    image_source: web::Data<ImageSource>,
+    documents: web::Data<Documents>,
 ) -> HttpResponse {
-    println!("Url-Path (Collection): {}", path.to_string());
-    let id = path.to_string();
-    match iiif_generator.get_ref().collection_for(&id) {
-        Ok(manifest) => HttpResponse::Ok().json(manifest),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    HttpResponse::Ok().json(CacheStats {
+        documents: documents.stats(),
+        images: image_source.cache_stats(),
+    })
+}
+
+fn json_response(validator: &Validator, media_type: &str, json: Bytes) -> HttpResponse {
+    validator
+        .headers(&mut HttpResponse::Ok())
+        .content_type(media_type)
+        .header(header::VARY, "Accept")
+        .body(json)
+}
+
+/// Media type of a manifest or collection after the `Accept` header:
+/// JSON-LD with the IIIF profile unless the client prefers plain JSON,
+/// None if it accepts neither.
+fn document_media_type(request: &HttpRequest) -> Option<&'static str> {
+    let accept = match request.headers().get(header::ACCEPT) {
+        Some(accept) => accept.to_str().unwrap_or(""),
+        None => return Some(IIIF_MEDIA_TYPE),
+    };
+    let json_ld = quality(accept, "application/ld+json", Some(IIIF_PROFILE));
+    let json = quality(accept, "application/json", None);
+    if json_ld > 0.0 && json_ld >= json {
+        Some(IIIF_MEDIA_TYPE)
+    } else if json > 0.0 {
+        Some("application/json")
+    } else {
+        None
+    }
+}
+
+/// Quality of a media type in an `Accept` header, from the most specific
+/// matching range. A range with another `profile` does not match.
+fn quality(accept: &str, media_type: &str, profile: Option<&str>) -> f32 {
+    let (main_type, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.split(',') {
+        let mut parts = range.split(';').map(str::trim);
+        let range_type = parts.next().unwrap_or("").to_ascii_lowercase();
+        let mut range_quality = 1.0;
+        let mut other_profile = false;
+        for parameter in parts {
+            let (name, value) = match parameter.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().trim_matches('"')),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("q") {
+                range_quality = value.parse().unwrap_or(0.0);
+            } else if name.eq_ignore_ascii_case("profile") {
+                other_profile = profile.is_some_and(|profile| {
+                    !value.split_whitespace().any(|listed| listed == profile)
+                });
+            }
+        }
+        let specificity = if range_type == media_type {
+            if other_profile {
+                continue;
+            }
+            3
+        } else if range_type == format!("{}/*", main_type) {
+            2
+        } else if range_type == "*/*" {
+            1
+        } else {
+            continue;
+        };
+        match best {
+            Some((best_specificity, _)) if best_specificity >= specificity => (),
+            _ => best = Some((specificity, range_quality)),
+        }
+    }
+    best.map_or(0.0, |(_, quality)| quality)
+}
+
+/// Serves sound, video and SVG files that have no image server, with
+/// support for single byte ranges so players can seek.
+#[get("/{id:.*}/files/{name}")]
+async fn media_file(
+    image_source: web::Data<ImageSource>,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let (id, name) = request_path(&request, "")
+        .rsplit_once("/files/")
+        .unwrap_or_default();
+    let (id, name) = (decode(id)?, decode(name)?);
+    println!("Url-Path (File): {}/{}", id, name);
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let deadline = Instant::now() + image_source.timeout();
+    let (file, format, length, range) = blocking(deadline, move || {
+        let (file_path, format) = image_source.file(&id, &name)?;
+        let (file, length, range) = open_range(&file_path, range.as_deref())
+            .map_err(|e| Error::UnreadableFile(file_path, e))?;
+        Ok((file, format, length, range))
+    })
+    .await?;
+    Ok(file_response(file, &format, length, range))
+}
+
+/// Opens a file and moves to the start of the requested range.
+fn open_range(path: &Path, range: Option<&str>) -> std::io::Result<(File, u64, Range)> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    let range = parse_range(range, length);
+    if let Range::Part(start, _) = range {
+        file.seek(SeekFrom::Start(start))?;
+    }
+    Ok((file, length, range))
+}
+
+fn file_response(file: File, format: &Format, length: u64, range: Range) -> HttpResponse {
+    let (mut response, start, end) = match range {
+        Range::Whole => (HttpResponse::Ok(), 0, length),
+        Range::Part(start, end) => {
+            let mut response = HttpResponse::PartialContent();
+            response.header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end - 1, length),
+            );
+            (response, start, end)
+        }
+        Range::Unsatisfiable => {
+            return HttpResponse::RangeNotSatisfiable()
+                .header(header::CONTENT_RANGE, format!("bytes */{}", length))
+                .finish()
+        }
+    };
+    if *format == Format::SVG {
+        // opened directly, SVG documents could run scripts on our origin
+        response.header(header::CONTENT_SECURITY_POLICY, "script-src 'none'");
+    }
+    let stream = SizedStream::new(end - start, read_chunks(file, end - start));
+    response
+        .content_type(format.media_type())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .body(Body::from_message(stream))
+}
+
+/// Reads a file in chunks while the response is sent, on the thread pool
+/// for blocking work like scanning.
+fn read_chunks(
+    file: File,
+    length: u64,
+) -> impl Stream<Item = std::result::Result<Bytes, actix_web::Error>> {
+    futures::stream::unfold((Some(file), length), |(file, remaining)| async move {
+        let mut file = file.filter(|_| remaining > 0)?;
+        let chunk = web::block(move || {
+            let mut buffer = vec![0u8; remaining.min(CHUNK_LENGTH) as usize];
+            let read = file.read(&mut buffer)?;
+            buffer.truncate(read);
+            Ok::<_, std::io::Error>((file, buffer))
+        })
+        .await;
+        match chunk {
+            Ok((_, buffer)) if buffer.is_empty() => None,
+            Ok((file, buffer)) => {
+                let remaining = remaining - buffer.len() as u64;
+                Some((Ok(Bytes::from(buffer)), (Some(file), remaining)))
+            }
+            Err(e) => Some((Err(e.into()), (None, 0))),
+        }
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum Range {
+    Whole,
+    /// Start and end, exclusive
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parses a Range header with a single byte range: `bytes=start-end`,
+/// `bytes=start-` or `bytes=-suffix`. Anything else is served whole.
+fn parse_range(header: Option<&str>, length: u64) -> Range {
+    let spec = match header.and_then(|header| header.strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Range::Whole,
+    };
+    let (first, last) = match spec.find('-') {
+        Some(dash) => (&spec[..dash], &spec[dash + 1..]),
+        None => return Range::Whole,
+    };
+    let bounds = match (first.parse::<u64>(), last.parse::<u64>()) {
+        (Ok(start), Ok(last)) if start <= last => Some((start, last.saturating_add(1).min(length))),
+        (Ok(start), Err(_)) if last.is_empty() => Some((start, length)),
+        // an empty suffix (`bytes=-0`) is unsatisfiable
+        (Err(_), Ok(suffix)) if first.is_empty() => Some((length.saturating_sub(suffix), length)),
+        _ => return Range::Whole,
+    };
+    match bounds {
+        Some((start, end)) if start < end => Range::Part(start, end),
+        _ => Range::Unsatisfiable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::http_api::{
+        blocking, decode, document_media_type, parse_range, representation, request_path, Range,
+        IIIF_MEDIA_TYPE,
+    };
+    use crate::validator::Validator;
+    use actix_web::http::header;
+    use actix_web::test::TestRequest;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(parse_range(None, 100), Range::Whole);
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), Range::Part(0, 10));
+        assert_eq!(parse_range(Some("bytes=90-"), 100), Range::Part(90, 100));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), Range::Part(90, 100));
+        assert_eq!(parse_range(Some("bytes=50-500"), 100), Range::Part(50, 100));
+        assert_eq!(parse_range(Some("bytes=100-"), 100), Range::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), Range::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-1,5-9"), 100), Range::Whole);
+    }
+
+    #[test]
+    fn negotiates_media_types() {
+        let media_type = |accept: &str| {
+            document_media_type(&TestRequest::with_header(header::ACCEPT, accept).to_http_request())
+        };
+        assert_eq!(
+            document_media_type(&TestRequest::default().to_http_request()),
+            Some(IIIF_MEDIA_TYPE)
+        );
+        assert_eq!(media_type("*/*"), Some(IIIF_MEDIA_TYPE));
+        assert_eq!(media_type("application/json"), Some("application/json"));
+        assert_eq!(
+            media_type("application/json, application/ld+json;q=0.9"),
+            Some("application/json")
+        );
+        assert_eq!(
+            media_type(
+                "application/ld+json;profile=\"http://iiif.io/api/presentation/3/context.json\""
+            ),
+            Some(IIIF_MEDIA_TYPE)
+        );
+        // a Presentation 2 client
+        assert_eq!(
+            media_type(
+                "application/ld+json;profile=\"http://iiif.io/api/presentation/2/context.json\""
+            ),
+            None
+        );
+        assert_eq!(media_type("text/html, */*;q=0"), None);
+    }
+
+    #[test]
+    fn gives_each_media_type_its_own_etag() {
+        let validator = Validator {
+            tag: "0123456789abcdef".to_owned(),
+            modified: UNIX_EPOCH,
+        };
+        assert_eq!(representation(&validator, IIIF_MEDIA_TYPE), validator);
+        let json = representation(&validator, "application/json");
+        assert_ne!(json.tag, validator.tag);
+        assert_eq!(json, representation(&validator, "application/json"));
+    }
+
+    #[test]
+    fn decodes_ids_from_raw_paths() {
+        let id = |uri: &str, suffix: &str| {
+            let request = TestRequest::with_uri(uri).to_http_request();
+            decode(request_path(&request, suffix)).unwrap()
+        };
+        assert_eq!(
+            id("/with%252Dmeta-x/manifest", "/manifest"),
+            "with%2Dmeta-x"
+        );
+        assert_eq!(id("/a%2Bb/collection", "/collection"), "a+b");
+        assert_eq!(id("/a/b/manifest", "/manifest"), "a/b");
+        assert_eq!(id("/%C3%BC/manifest", "/manifest"), "ü");
+    }
+
+    #[test]
+    fn gives_up_on_slow_scans() {
+        let mut system = actix_rt::System::new("test");
+        let slow = system.block_on(blocking(Instant::now(), || {
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(())
+        }));
+        assert!(matches!(slow, Err(Error::Timeout)));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let fast = system.block_on(blocking(deadline, || Ok(7)));
+        assert_eq!(fast.unwrap(), 7);
     }
 }
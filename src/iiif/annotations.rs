@@ -62,7 +62,11 @@ impl Annotation {
         motivation: Motivation,
     ) -> Annotation {
         let id = match resource {
-            Resource::Image(_) => Annotation::id(presentation_api, item_id, index, "image"),
+            Resource::Image(_) | Resource::SpecificResource(_) => {
+                Annotation::id(presentation_api, item_id, index, "image")
+            }
+            Resource::Sound(_) => Annotation::id(presentation_api, item_id, index, "sound"),
+            Resource::Video(_) => Annotation::id(presentation_api, item_id, index, "video"),
         };
         Annotation {
             id,
@@ -1,9 +1,12 @@
+use crate::config::OrientationMode;
 use crate::iiif::annotations::{Annotation, AnnotationPage};
-use crate::iiif::metadata::Metadata;
-use crate::iiif::resources::{IiifImage, Resource};
+use crate::iiif::metadata::{Metadata, Value};
+use crate::iiif::properties::CanvasProperties;
+use crate::iiif::resources::{file_id, IiifImage, Resource, Sound, SpecificResource, Video};
 use crate::iiif::types::Id;
 use crate::iiif::types::Uri;
 use crate::image::source::Image;
+use crate::image::Kind;
 
 use serde::Serialize;
 
@@ -43,46 +46,115 @@ impl Manifest {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn add_image(
         &mut self,
         image_api: &str,
         presentation_api: &str,
         item_id: &Id,
         image_id: &Id,
-        label: &str,
+        properties: CanvasProperties,
         image: &Image,
+        orientation: OrientationMode,
     ) {
         let index = self.items.len();
-        let mut canvas = Canvas::new(
-            presentation_api,
-            item_id,
-            index,
-            label,
-            image.width,
-            image.height,
-        );
-        let image_resource = IiifImage::new(image_api, image_id, image);
-        let annotation = Annotation::new_painting(
-            presentation_api,
-            item_id,
-            index,
-            Resource::Image(image_resource),
-            (&canvas.id).clone(),
-        );
+        let stored = (image.width, image.height);
+        let displayed = if image.orientation.swaps_dimensions() {
+            (image.height, image.width)
+        } else {
+            stored
+        };
+        let (width, height) = match orientation {
+            OrientationMode::Ignore => stored,
+            OrientationMode::Apply | OrientationMode::Selector => displayed,
+        };
+        let mut canvas = Canvas::new(presentation_api, item_id, index, properties, width, height);
+        let body = match orientation {
+            _ if !image.format.has_image_service() => {
+                let id = file_id(presentation_api, item_id, &image.name);
+                Resource::Image(IiifImage::file(id, image))
+            }
+            OrientationMode::Selector if !image.orientation.is_identity() => {
+                let source = IiifImage::new(image_api, image_id, image, stored.0, stored.1);
+                Resource::SpecificResource(SpecificResource::rotated(source, &image.orientation))
+            }
+            _ => Resource::Image(IiifImage::new(image_api, image_id, image, width, height)),
+        };
+        let annotation =
+            Annotation::new_painting(presentation_api, item_id, index, body, canvas.id.clone());
         let annotation_page =
             AnnotationPage::new(presentation_api, item_id, index, vec![annotation]);
         &canvas.add_item(annotation_page);
         self.items.push(canvas);
     }
+
+    /// Adds a sound or video, served by forager itself.
+    pub fn add_timed(
+        &mut self,
+        presentation_api: &str,
+        item_id: &Id,
+        properties: CanvasProperties,
+        image: &Image,
+        orientation: OrientationMode,
+    ) {
+        let index = self.items.len();
+        let duration = image.duration.unwrap_or_default();
+        let id = file_id(presentation_api, item_id, &image.name);
+        let mut canvas = Canvas::new(presentation_api, item_id, index, properties, 0, 0);
+        canvas.duration = Some(duration);
+        let body = match image.kind {
+            Kind::Video => {
+                // players apply the rotation of the track
+                let (width, height) = match orientation {
+                    OrientationMode::Apply | OrientationMode::Selector
+                        if image.orientation.swaps_dimensions() =>
+                    {
+                        (image.height, image.width)
+                    }
+                    _ => (image.width, image.height),
+                };
+                canvas.width = Some(width);
+                canvas.height = Some(height);
+                Resource::Video(Video::new(id, image, width, height, duration))
+            }
+            _ => {
+                canvas.width = None;
+                canvas.height = None;
+                Resource::Sound(Sound::new(id, image, duration))
+            }
+        };
+        let annotation =
+            Annotation::new_painting(presentation_api, item_id, index, body, canvas.id.clone());
+        let annotation_page =
+            AnnotationPage::new(presentation_api, item_id, index, vec![annotation]);
+        canvas.add_item(annotation_page);
+        self.items.push(canvas);
+    }
 }
 
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 pub struct Canvas {
     id: Uri,
-    label: String,
-    height: u32,
-    width: u32,
+    label: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    /// In seconds, for sound and video
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<Value>,
+    #[serde(rename = "requiredStatement", skip_serializing_if = "Option::is_none")]
+    required_statement: Option<Metadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rights: Option<String>,
+    #[serde(rename = "navDate", skip_serializing_if = "Option::is_none")]
+    nav_date: Option<String>,
+    /// Labels embedded in the image file
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    metadata: Vec<Metadata>,
     items: Vec<AnnotationPage>,
 }
 
@@ -97,15 +169,21 @@ impl Canvas {
         presentation_api: &str,
         item_id: &Id,
         index: usize,
-        label: &str,
+        properties: CanvasProperties,
         width: u32,
         height: u32,
     ) -> Canvas {
         Canvas {
             id: Canvas::id(presentation_api, item_id, index),
-            label: label.to_owned(),
-            height,
-            width,
+            label: properties.label,
+            height: Some(height),
+            width: Some(width),
+            duration: None,
+            summary: properties.summary,
+            required_statement: properties.required_statement,
+            rights: properties.rights,
+            nav_date: properties.nav_date,
+            metadata: properties.metadata,
             items: Vec::new(),
         }
     }
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(untagged)]
@@ -6,6 +7,8 @@ pub enum Value {
     Single(String),
     Many(Vec<String>),
     Multilang(Vec<LocalizedValue>),
+    /// Presentation API 3 language map, `none` for unknown languages
+    LanguageMap(BTreeMap<String, Vec<String>>),
 }
 
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
@@ -27,27 +30,37 @@ impl LocalizedValue {
 
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
 pub struct Metadata {
-    pub label: String,
+    pub label: Value,
     pub value: Value,
 }
 
 impl Metadata {
     pub fn key_value<S: Into<String>>(label: S, value: S) -> Metadata {
         Metadata {
-            label: label.into(),
+            label: Value::Single(label.into()),
             value: Value::Single(value.into()),
         }
     }
     pub fn list<S: Into<String>>(label: S, values: Vec<String>) -> Metadata {
         Metadata {
-            label: label.into(),
+            label: Value::Single(label.into()),
             value: Value::Many(values),
         }
     }
     pub fn localized<S: Into<String>>(label: S, values: Vec<LocalizedValue>) -> Metadata {
         Metadata {
-            label: label.into(),
+            label: Value::Single(label.into()),
             value: Value::Multilang(values),
         }
     }
+
+    pub fn language_map(
+        label: BTreeMap<String, Vec<String>>,
+        value: BTreeMap<String, Vec<String>>,
+    ) -> Metadata {
+        Metadata {
+            label: Value::LanguageMap(label),
+            value: Value::LanguageMap(value),
+        }
+    }
 }
@@ -2,17 +2,20 @@ pub mod annotations;
 pub mod collections;
 pub mod manifests;
 pub mod metadata;
+pub mod properties;
 pub mod resources;
 pub mod types;
 
-use crate::config::Config;
+use crate::config::{Config, Urls};
+use crate::error::Result;
 use crate::iiif::collections::Collection;
 use crate::iiif::manifests::Manifest;
+use crate::iiif::properties::CanvasProperties;
 use crate::iiif::types::Id;
 use crate::image::source::Image;
+use crate::image::{Kind, Resolution};
 use crate::meta::Meta;
 
-use std::error::Error;
 use std::ffi::OsStr;
 
 pub struct IiifGenerator {
@@ -23,66 +26,134 @@ impl IiifGenerator {
     pub fn new(config: Config) -> IiifGenerator {
         IiifGenerator { config }
     }
-    pub fn manifest_for(&self, id: &str, images: Vec<Image>) -> Result<Manifest, String> {
-        let os_sep = std::path::MAIN_SEPARATOR.to_string();
-        let path = id.replace(&self.config.urls.path_sep, os_sep.as_str());
-        let source_path = self.config.serving.path.join(path);
 
-        let item_id = Id::new(id.replace("/", &self.config.urls.path_sep));
-        let context = Meta::load_or_default(&source_path);
+    /// The configured URLs, for ids in documents.
+    pub fn urls(&self) -> &Urls {
+        &self.config.urls
+    }
+
+    /// The manifest of a directory, with ids built from `urls`.
+    pub fn manifest_for(&self, id: &str, images: Vec<Image>, urls: &Urls) -> Result<Manifest> {
+        let source_path = self.config.directory(id)?;
+
+        // the same id for `a-b` and `a/b`
+        let item_id = Id::new(urls.id(&urls.names(id).unwrap_or_default()));
+        let context = Meta::load(&source_path)?;
         let mut manifest = Manifest::new(
-            &self.config.urls.presentation_api,
+            &urls.presentation_api,
             &item_id,
             &id,
             context.metadata,
             context.description,
         );
-        for image in images {
-            let image_id = Id::new(
-                format!(
-                    "{}{}{}",
-                    item_id.value, &self.config.urls.path_sep, image.name
-                )
-                .as_str(),
-            );
-            let urls = &self.config.urls;
-            manifest.add_image(
-                &urls.image_api,
-                &urls.presentation_api,
-                &item_id,
-                &image_id,
-                &image.name,
-                &image,
-            )
+        let mapping = self.config.mapping.merge(&context.mapping);
+        let resolution = context.dpi.map(Resolution::from_dpi);
+        for mut image in images {
+            if resolution.is_some() {
+                image.resolution = resolution;
+            }
+            let file_name = urls.escape(&image.name);
+            let (file_id, label) = match &image.page {
+                Some(page) => (
+                    urls.page_id(&file_name, page.index),
+                    format!("{} ({}/{})", image.name, page.index + 1, page.count),
+                ),
+                None => (file_name, image.name.clone()),
+            };
+            let image_id = Id::image(urls.join(&item_id.value, &file_id));
+            let mut properties = CanvasProperties::new(&label, &image.labels, &mapping);
+            properties.add_integrity(&image.integrity);
+            match image.kind {
+                Kind::Image => manifest.add_image(
+                    &urls.image_api,
+                    &urls.presentation_api,
+                    &item_id,
+                    &image_id,
+                    properties,
+                    &image,
+                    self.config.images.orientation,
+                ),
+                Kind::Sound | Kind::Video => manifest.add_timed(
+                    &urls.presentation_api,
+                    &item_id,
+                    properties,
+                    &image,
+                    self.config.images.orientation,
+                ),
+            }
         }
         Ok(manifest)
     }
 
-    pub fn collection_for(&self, id: &str) -> Result<Collection, Box<dyn Error>> {
-        let os_sep = std::path::MAIN_SEPARATOR.to_string();
-        let path = id.replace(&self.config.urls.path_sep, os_sep.as_str());
-        let source_path = self.config.serving.path.join(path);
+    /// The collection of a directory, with ids built from `urls`.
+    pub fn collection_for(&self, id: &str, urls: &Urls) -> Result<Collection> {
+        let source_path = self.config.directory(id)?;
 
         let mut directory_paths: Vec<_> = std::fs::read_dir(source_path)?
-            .map(|entry| entry.unwrap().path())
-            .filter(|path| path.is_dir())
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() && self.config.is_served(path))
             .collect();
         directory_paths.sort();
+        let parent = urls.id(&urls.names(id).unwrap_or_default());
         let mut collection = Collection::new();
         for path in directory_paths {
-            let name = path.file_name().and_then(OsStr::to_str);
-            if name.is_none() {
-                continue;
-            }
-            let item_id = Id::new(format!(
-                "{}{}{}",
-                id,
-                &self.config.urls.path_sep,
-                name.unwrap()
-            ));
-            let manifest_id = Manifest::id(&self.config.urls.presentation_api, &item_id);
+            let name = match path.file_name().and_then(OsStr::to_str) {
+                Some(name) => name,
+                None => continue,
+            };
+            let item_id = Id::new(urls.join(&parent, &urls.escape(name)));
+            let manifest_id = Manifest::id(&urls.presentation_api, &item_id);
             collection.add_manifest(manifest_id);
         }
         Ok(collection)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::iiif::IiifGenerator;
+    use crate::image::source::Image;
+
+    #[test]
+    fn shows_the_integrity_of_each_image() {
+        let root = std::env::temp_dir().join(format!("forager-integrity-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        let sample = std::fs::read("sample/watergate/simple/MOV_0646000.png").unwrap();
+        // signature and IHDR, with and without the end chunk
+        let mut intact = sample[..33].to_vec();
+        intact.extend_from_slice(b"\0\0\0\0IEND\xae\x42\x60\x82");
+        std::fs::write(root.join("a/intact.png"), intact).unwrap();
+        std::fs::write(root.join("a/truncated.png"), &sample[..33]).unwrap();
+        let mut config: Config = serde_yaml::from_str(
+            "
+            serving: {path: samples, host: localhost, port: 7890}
+            urls: {path sep: '-', image api: 'http://i', presentation api: 'http://p'}
+            ",
+        )
+        .unwrap();
+        config.serving.path = root.clone();
+        let mut images = Vec::new();
+        for name in &["intact.png", "truncated.png"] {
+            images.extend(Image::for_file(&root.join("a").join(name), &config.images).unwrap());
+        }
+        let urls = config.urls.clone();
+        let manifest = IiifGenerator::new(config)
+            .manifest_for("a", images, &urls)
+            .unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let manifest = serde_json::to_value(&manifest).unwrap();
+        let integrity = |index: usize| {
+            manifest["items"][index]["metadata"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|entry| entry["label"] == "Integrity")
+                .map(|entry| entry["value"].clone())
+        };
+        assert_eq!(integrity(0).unwrap(), "intact");
+        assert_eq!(integrity(1).unwrap(), "damaged: file is truncated");
+    }
+}
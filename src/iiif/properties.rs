@@ -0,0 +1,238 @@
+use crate::config::Mapping;
+use crate::iiif::metadata::{Metadata, Value};
+use crate::image::label::{Label, LabelValue, Translation};
+use crate::image::Integrity;
+
+use std::collections::BTreeMap;
+
+/// Descriptive properties of a canvas, from the labels embedded in the file.
+#[derive(Debug, PartialEq)]
+pub struct CanvasProperties {
+    pub label: Value,
+    pub summary: Option<Value>,
+    pub required_statement: Option<Metadata>,
+    pub rights: Option<String>,
+    pub nav_date: Option<String>,
+    pub metadata: Vec<Metadata>,
+}
+
+impl CanvasProperties {
+    /// Routes labels to properties as configured in `mapping`, `label` is
+    /// used if no embedded label is mapped to the canvas label.
+    pub fn new(label: &str, labels: &[Label], mapping: &Mapping) -> CanvasProperties {
+        let mut used = vec![false; labels.len()];
+        let mut route = |keys: &[String], accept: &dyn Fn(&LabelValue) -> bool| {
+            let found = keys.iter().find_map(|key| {
+                labels.iter().enumerate().find(|(index, label)| {
+                    !used[*index] && &label.key == key && accept(&label.value)
+                })
+            });
+            found.map(|(index, label)| {
+                used[index] = true;
+                label
+            })
+        };
+        let any = |_: &LabelValue| true;
+        let title = route(&mapping.label, &any).map(|label| text_value(&label.value));
+        let summary = route(&mapping.summary, &any).map(|label| html_value(&label.value));
+        let required_statement = route(&mapping.required_statement, &any)
+            .map(|label| label_metadata(label, &mapping.rename));
+        let rights = route(&mapping.rights, &|value| {
+            matches!(value, LabelValue::Uri(_))
+        })
+        .map(|label| label.value.to_string());
+        let nav_date = route(&mapping.nav_date, &|value| {
+            matches!(value, LabelValue::Timestamp(_))
+        })
+        .and_then(|label| match &label.value {
+            LabelValue::Timestamp(timestamp) => Some(timestamp.nav_date()),
+            _ => None,
+        });
+        let metadata = labels
+            .iter()
+            .zip(used)
+            .filter(|(label, used)| !used && !mapping.ignore.contains(&label.key))
+            .map(|(label, _)| label_metadata(label, &mapping.rename))
+            .collect();
+        CanvasProperties {
+            label: title.unwrap_or_else(|| Value::Single(label.to_owned())),
+            summary,
+            required_statement,
+            rights,
+            nav_date,
+            metadata,
+        }
+    }
+
+    /// Adds the outcome of the integrity checks as metadata, if they ran.
+    pub fn add_integrity(&mut self, integrity: &Integrity) {
+        let outcome = match integrity {
+            Integrity::Unchecked => return,
+            Integrity::Intact => "intact".to_owned(),
+            Integrity::Damaged(defects) => {
+                let defects: Vec<_> = defects.iter().map(ToString::to_string).collect();
+                format!("damaged: {}", defects.join("; "))
+            }
+        };
+        self.metadata
+            .push(Metadata::key_value("Integrity", outcome.as_str()));
+    }
+}
+
+/// A value as plain text, for canvas labels.
+fn text_value(value: &LabelValue) -> Value {
+    match value {
+        LabelValue::Localized(translations) => Value::LanguageMap(language_values(translations)),
+        LabelValue::List(values) => Value::Many(values.iter().map(|v| v.to_string()).collect()),
+        value => Value::Single(value.to_string()),
+    }
+}
+
+/// A value for summaries and metadata, which may contain links.
+fn html_value(value: &LabelValue) -> Value {
+    match value {
+        LabelValue::Uri(uri) => Value::Single(link(uri)),
+        LabelValue::List(values) => Value::Many(
+            values
+                .iter()
+                .map(|value| match value {
+                    LabelValue::Uri(uri) => link(uri),
+                    value => value.to_string(),
+                })
+                .collect(),
+        ),
+        value => text_value(value),
+    }
+}
+
+fn link(uri: &str) -> String {
+    let uri = uri
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+    format!("<a href=\"{}\">{}</a>", uri, uri)
+}
+
+fn language_values(translations: &[Translation]) -> BTreeMap<String, Vec<String>> {
+    let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for translation in translations {
+        values
+            .entry(language(&translation.language))
+            .or_default()
+            .push(translation.value.clone());
+    }
+    values
+}
+
+fn label_metadata(label: &Label, rename: &BTreeMap<String, String>) -> Metadata {
+    let key = rename.get(&label.key).unwrap_or(&label.key);
+    let label_value = match &label.value {
+        LabelValue::Localized(translations) => {
+            let mut keys: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            for translation in translations {
+                // a renamed keyword replaces untranslated keys
+                let translated = if translation.key == label.key {
+                    key
+                } else {
+                    &translation.key
+                };
+                let language_keys = keys.entry(language(&translation.language)).or_default();
+                if !language_keys.contains(translated) {
+                    language_keys.push(translated.clone());
+                }
+            }
+            Value::LanguageMap(keys)
+        }
+        _ => Value::Single(key.clone()),
+    };
+    Metadata {
+        label: label_value,
+        value: html_value(&label.value),
+    }
+}
+
+fn language(language: &str) -> String {
+    if language.is_empty() {
+        "none".to_owned()
+    } else {
+        language.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::config::Mapping;
+    use crate::iiif::metadata::{Metadata, Value};
+    use crate::iiif::properties::CanvasProperties;
+    use crate::image::label::{Label, LabelValue};
+
+    #[test]
+    fn mapping() {
+        let labels = vec![
+            Label::parse("Title", "Harbour"),
+            Label::parse("Description", "The harbour at night"),
+            Label::parse("Copyright", "Harbour Museum"),
+            Label::parse("License", "see website"),
+            Label::parse("WebStatement", "https://example.org/?a=1&b=2"),
+            Label::parse("Author", "A. Scanner"),
+            Label::parse("Software", "ScanIt 2.0"),
+            Label::parse("DateTimeOriginal", "2020:04:21 22:34:18"),
+            Label::new("FNumber", LabelValue::rational(28, 10)),
+        ];
+        let strings = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect();
+        let mut mapping = Mapping {
+            label: strings(&["Headline", "Title"]),
+            summary: strings(&["Description"]),
+            required_statement: strings(&["Copyright"]),
+            rights: strings(&["License"]),
+            nav_date: strings(&["DateTimeOriginal"]),
+            ignore: strings(&["Software"]),
+            ..Mapping::default()
+        };
+        mapping
+            .rename
+            .insert("Author".to_owned(), "Creator".to_owned());
+        let properties = CanvasProperties::new("scan.png", &labels, &mapping);
+        assert_eq!(properties.label, Value::Single("Harbour".to_owned()));
+        assert_eq!(
+            properties.summary,
+            Some(Value::Single("The harbour at night".to_owned()))
+        );
+        assert_eq!(
+            properties.required_statement,
+            Some(Metadata::key_value("Copyright", "Harbour Museum"))
+        );
+        // not a URL
+        assert_eq!(properties.rights, None);
+        assert_eq!(properties.nav_date, Some("2020-04-21T22:34:18Z".to_owned()));
+        assert_eq!(
+            properties.metadata,
+            vec![
+                Metadata::key_value("License", "see website"),
+                Metadata::key_value(
+                    "WebStatement",
+                    "<a href=\"https://example.org/?a=1&amp;b=2\">https://example.org/?a=1&amp;b=2</a>"
+                ),
+                Metadata::key_value("Creator", "A. Scanner"),
+                Metadata::key_value("FNumber", "2.8"),
+            ]
+        );
+
+        mapping.rights = strings(&["License", "WebStatement"]);
+        let properties = CanvasProperties::new("scan.png", &labels, &mapping);
+        assert_eq!(
+            properties.rights,
+            Some("https://example.org/?a=1&b=2".to_owned())
+        );
+
+        let properties = CanvasProperties::new("scan.png", &labels, &Mapping::default());
+        assert_eq!(properties.label, Value::Single("scan.png".to_owned()));
+        assert_eq!(properties.metadata.len(), labels.len());
+        assert_eq!(
+            properties.metadata[7],
+            Metadata::key_value("DateTimeOriginal", "2020-04-21T22:34:18")
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::iiif::types::{Id, Uri};
 use crate::image::source::Image;
-use crate::image::Format;
+use crate::image::{Format, Orientation, Tiling};
 use serde::Serialize;
 
 #[derive(Debug, Serialize)]
@@ -15,14 +15,93 @@ pub struct Service {
 #[serde(untagged)]
 pub enum Resource {
     Image(IiifImage),
+    SpecificResource(SpecificResource),
+    Sound(Sound),
+    Video(Video),
+}
+
+/// Url of a file served by forager itself, for media without an image server.
+pub fn file_id(presentation_api: &str, item_id: &Id, name: &str) -> Uri {
+    Uri::new(format!(
+        "{}/{}/files/{}",
+        presentation_api,
+        item_id.encoded,
+        Id::new(name).encoded
+    ))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct Sound {
+    id: Uri,
+    format: String,
+    duration: f64,
+}
+
+impl Sound {
+    pub fn new(id: Uri, image: &Image, duration: f64) -> Sound {
+        Sound {
+            id,
+            format: image.media_type().to_owned(),
+            duration,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct Video {
+    id: Uri,
+    format: String,
+    width: u32,
+    height: u32,
+    duration: f64,
+}
+
+impl Video {
+    pub fn new(id: Uri, image: &Image, width: u32, height: u32, duration: f64) -> Video {
+        Video {
+            id,
+            format: image.media_type().to_owned(),
+            width,
+            height,
+            duration,
+        }
+    }
+}
+
+/// An image transformed by the Image API, e.g. rotated for display.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct SpecificResource {
+    source: IiifImage,
+    selector: ImageApiSelector,
+}
+
+impl SpecificResource {
+    pub fn rotated(source: IiifImage, orientation: &Orientation) -> SpecificResource {
+        SpecificResource {
+            source,
+            selector: ImageApiSelector {
+                rotation: orientation.to_string(),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
+pub struct ImageApiSelector {
+    rotation: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename = "Image")]
 pub struct IiifImage {
     id: Uri,
     format: String,
-    service: ImageService2,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service: Option<ImageService2>,
     width: u32,
     height: u32,
 }
@@ -33,36 +112,166 @@ impl IiifImage {
             "{}/{}/full/full/0/default.{}",
             image_api,
             image_id.encoded,
-            format.extension()
+            delivered(format).extension()
         ))
     }
 
-    pub fn new(image_api: &str, image_id: &Id, image: &Image) -> IiifImage {
+    /// The size is the one delivered by the image server, which differs
+    /// from the stored size if the server applies the orientation.
+    pub fn new(
+        image_api: &str,
+        image_id: &Id,
+        image: &Image,
+        width: u32,
+        height: u32,
+    ) -> IiifImage {
+        let service_id = ImageService2::id(image_api, image_id);
         IiifImage {
             id: IiifImage::id(image_api, image_id, &image.format),
-            format: image.format.media_type().to_owned(),
-            service: ImageService2::new(ImageService2::id(image_api, image_id)),
+            format: delivered(&image.format).media_type().to_owned(),
+            service: Some(ImageService2::new(service_id, image, width, height)),
+            width,
+            height,
+        }
+    }
+
+    /// An image file served as it is, without an image service.
+    pub fn file(id: Uri, image: &Image) -> IiifImage {
+        IiifImage {
+            id,
+            format: image.media_type().to_owned(),
+            service: None,
             width: image.width,
             height: image.height,
         }
     }
 }
 
+/// Format requested from the image server. DICOM is rendered, since image
+/// servers do not deliver it and viewers could not show it.
+fn delivered(format: &Format) -> &Format {
+    match format {
+        Format::DICOM => &Format::JPEG,
+        format => format,
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 pub struct ImageService2 {
     id: Uri,
     profile: String,
+    width: u32,
+    height: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tiles: Vec<Tile>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    service: Vec<PhysicalDimensions>,
 }
 
 impl ImageService2 {
     pub fn id(image_api: &str, image_id: &Id) -> Uri {
         Uri::new(format!("{}/{}", image_api, image_id.encoded))
     }
-    fn new(id: Uri) -> ImageService2 {
+    fn new(id: Uri, image: &Image, width: u32, height: u32) -> ImageService2 {
+        let tiles = match &image.tiling {
+            Some(tiling) => vec![Tile::new(tiling)],
+            None => Vec::new(),
+        };
+        let service = image
+            .resolution
+            .and_then(|resolution| resolution.millimetres_per_pixel())
+            .map(PhysicalDimensions::millimetres)
+            .into_iter()
+            .collect();
         ImageService2 {
             id,
             profile: "level2".to_owned(),
+            width,
+            height,
+            tiles,
+            service,
         }
     }
 }
+
+/// Physical Dimensions service: the size of a pixel, so viewers can show
+/// rulers.
+#[derive(Debug, Serialize)]
+pub struct PhysicalDimensions {
+    #[serde(rename = "@context")]
+    context: Uri,
+    profile: Uri,
+    #[serde(rename = "physicalScale")]
+    physical_scale: f64,
+    #[serde(rename = "physicalUnits")]
+    physical_units: String,
+}
+
+impl PhysicalDimensions {
+    fn millimetres(scale: f64) -> PhysicalDimensions {
+        PhysicalDimensions {
+            context: Uri::new("http://iiif.io/api/annex/services/physdim/1/context.json"),
+            profile: Uri::new("http://iiif.io/api/annex/services/physdim"),
+            physical_scale: scale,
+            physical_units: "mm".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tile {
+    width: u32,
+    height: u32,
+    #[serde(rename = "scaleFactors")]
+    scale_factors: Vec<u32>,
+}
+
+impl Tile {
+    fn new(tiling: &Tiling) -> Tile {
+        Tile {
+            width: tiling.width,
+            height: tiling.height,
+            scale_factors: (0..u32::from(tiling.levels).min(32))
+                .map(|level| 1 << level)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::iiif::resources::IiifImage;
+    use crate::iiif::types::Id;
+    use crate::image::source::Image;
+    use crate::image::{Format, Resolution};
+
+    #[test]
+    fn renders_dicom_as_jpeg() {
+        let image = Image::new("scan.dcm".to_owned(), Format::DICOM, 256, 512);
+        let body = IiifImage::new("http://images", &Id::new("a"), &image, 256, 512);
+        let json = serde_json::to_value(&body).unwrap();
+        assert_eq!(json["id"], "http://images/a/full/full/0/default.jpg");
+        assert_eq!(json["format"], "image/jpeg");
+    }
+
+    #[test]
+    fn describes_the_physical_size_of_pixels() {
+        let mut image = Image::new("scan.tif".to_owned(), Format::TIFF, 256, 512);
+        image.resolution = Some(Resolution::from_dpi(254.0));
+        let body = IiifImage::new("http://images", &Id::new("a"), &image, 256, 512);
+        let json = serde_json::to_value(&body).unwrap();
+        let service = &json["service"]["service"][0];
+        assert_eq!(
+            service["profile"],
+            "http://iiif.io/api/annex/services/physdim"
+        );
+        assert!((service["physicalScale"].as_f64().unwrap() - 0.1).abs() < 1e-9);
+        assert_eq!(service["physicalUnits"], "mm");
+
+        image.resolution = None;
+        let body = IiifImage::new("http://images", &Id::new("a"), &image, 256, 512);
+        let json = serde_json::to_value(&body).unwrap();
+        assert!(json["service"].get("service").is_none());
+    }
+}
@@ -1,14 +1,34 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::Serialize;
 
+/// Characters left as they are in URLs: unreserved ones after RFC 3986 and
+/// `/`, which only appears in ids with `path sep: /`
+const URL_SAFE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+/// URL_SAFE without `/`
+const SEGMENT_SAFE: &AsciiSet = &URL_SAFE.add(b'/');
+
 pub struct Id {
     pub value: String,
+    /// The value as part of a URL path
     pub encoded: String,
 }
 
 impl Id {
     pub fn new<S: Into<String>>(value: S) -> Id {
         let value = value.into();
-        let encoded = value.replace("/", "%2F");
+        let encoded = utf8_percent_encode(&value, URL_SAFE).to_string();
+        Id { value, encoded }
+    }
+
+    /// Id for the Image API, which needs `/` encoded, too.
+    pub fn image<S: Into<String>>(value: S) -> Id {
+        let value = value.into();
+        let encoded = utf8_percent_encode(&value, SEGMENT_SAFE).to_string();
         Id { value, encoded }
     }
 }
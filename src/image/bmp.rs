@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use crate::image::invalid_data;
+
+/// Sizes of the DIB headers from BITMAPCOREHEADER to BITMAPV5HEADER
+pub const DIB_HEADER_SIZES: [u32; 6] = [12, 40, 52, 56, 108, 124];
+
+#[derive(Debug, PartialEq)]
+pub struct BMP {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl BMP {
+    pub fn load(path: &PathBuf) -> std::io::Result<BMP> {
+        let mut file = File::open(path)?;
+        read_bmp(&mut file)
+    }
+}
+
+/// Reads the size from the file header and the DIB header that follows it.
+pub fn read_bmp<R: Read>(reader: &mut R) -> std::io::Result<BMP> {
+    let mut header = [0u8; 26];
+    reader.read_exact(&mut header)?;
+    if &header[..2] != b"BM" {
+        return Err(invalid_data("bmp", "missing BM signature"));
+    }
+    let dib_size = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+    let (width, height) = match dib_size {
+        // BITMAPCOREHEADER
+        12 => (
+            u32::from(u16::from_le_bytes([header[18], header[19]])),
+            u32::from(u16::from_le_bytes([header[20], header[21]])),
+        ),
+        size if size >= 40 => (
+            i32::from_le_bytes([header[18], header[19], header[20], header[21]]).unsigned_abs(),
+            // negative for top-down bitmaps
+            i32::from_le_bytes([header[22], header[23], header[24], header[25]]).unsigned_abs(),
+        ),
+        _ => return Err(invalid_data("bmp", "unknown DIB header")),
+    };
+    Ok(BMP { width, height })
+}
@@ -0,0 +1,334 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::PathBuf;
+
+use crate::image::label::Label;
+use crate::image::{invalid_data, read_vec};
+
+pub const PREAMBLE_LENGTH: usize = 128;
+pub const SIGNATURE: &[u8] = b"DICM";
+
+const TRANSFER_SYNTAX: Tag = Tag(0x0002, 0x0010);
+const NUMBER_OF_FRAMES: Tag = Tag(0x0028, 0x0008);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const PIXEL_DATA: Tag = Tag(0x7fe0, 0x0010);
+const ITEM_GROUP: u16 = 0xfffe;
+const SEQUENCE_DELIMITER: Tag = Tag(0xfffe, 0xe0dd);
+const UNDEFINED_LENGTH: u32 = 0xffff_ffff;
+
+const IMPLICIT_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+const EXPLICIT_BIG_ENDIAN: &str = "1.2.840.10008.1.2.2";
+const DEFLATED: &str = "1.2.840.10008.1.2.1.99";
+
+// Values of the tags we read are short, everything else is skipped
+const MAX_VALUE_LENGTH: u32 = 1024;
+const MAX_DEPTH: u32 = 16;
+// One canvas is made for each frame
+const MAX_FRAMES: u32 = 10_000;
+
+/// Tags exposed as labels. Only these are ever read, all other values are
+/// skipped, and `is_identifying` is checked again before a value is kept.
+/// Study date and descriptions are left out on purpose, they can identify
+/// patients.
+const LABEL_TAGS: &[(Tag, &str)] = &[
+    (Tag(0x0008, 0x0060), "Modality"),
+    (Tag(0x0008, 0x0070), "Manufacturer"),
+    (Tag(0x0018, 0x0015), "BodyPartExamined"),
+];
+
+/// Tags that identify patients, staff, institutions or studies, taken from
+/// the basic profile of DICOM PS3.15 as a second line of defense behind
+/// `LABEL_TAGS`. The whole patient group is included.
+const IDENTIFYING_TAGS: &[Tag] = &[
+    Tag(0x0008, 0x0014), // InstanceCreatorUID
+    Tag(0x0008, 0x0018), // SOPInstanceUID
+    Tag(0x0008, 0x0020), // StudyDate
+    Tag(0x0008, 0x0050), // AccessionNumber
+    Tag(0x0008, 0x0080), // InstitutionName
+    Tag(0x0008, 0x0081), // InstitutionAddress
+    Tag(0x0008, 0x0090), // ReferringPhysicianName
+    Tag(0x0008, 0x0092), // ReferringPhysicianAddress
+    Tag(0x0008, 0x0094), // ReferringPhysicianTelephoneNumbers
+    Tag(0x0008, 0x1010), // StationName
+    Tag(0x0008, 0x1030), // StudyDescription, free text
+    Tag(0x0008, 0x103e), // SeriesDescription, free text
+    Tag(0x0008, 0x1040), // InstitutionalDepartmentName
+    Tag(0x0008, 0x1048), // PhysiciansOfRecord
+    Tag(0x0008, 0x1050), // PerformingPhysicianName
+    Tag(0x0008, 0x1060), // NameOfPhysiciansReadingStudy
+    Tag(0x0008, 0x1070), // OperatorsName
+    Tag(0x0018, 0x1000), // DeviceSerialNumber
+    Tag(0x0020, 0x000d), // StudyInstanceUID
+    Tag(0x0020, 0x000e), // SeriesInstanceUID
+    Tag(0x0020, 0x0010), // StudyID
+    Tag(0x0020, 0x0052), // FrameOfReferenceUID
+    Tag(0x0032, 0x1032), // RequestingPhysician
+    Tag(0x0040, 0x0006), // ScheduledPerformingPhysicianName
+    Tag(0x0040, 0xa123), // PersonName
+];
+const PATIENT_GROUP: u16 = 0x0010;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Tag(pub u16, pub u16);
+
+/// True for tags that must never leave the server.
+pub fn is_identifying(tag: Tag) -> bool {
+    tag.0 == PATIENT_GROUP || IDENTIFYING_TAGS.contains(&tag)
+}
+
+/// Image size, frame count and the de-identified label tags of a DICOM
+/// file (Part 10 format with preamble and file meta information).
+#[derive(Debug, Default, PartialEq)]
+pub struct DICOM {
+    pub rows: u32,
+    pub columns: u32,
+    pub frames: u32,
+    pub elements: Vec<(Tag, String)>,
+}
+
+impl DICOM {
+    pub fn load(path: &PathBuf) -> std::io::Result<DICOM> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_dicom(&mut file)
+    }
+
+    pub fn labels(&self) -> Vec<Label> {
+        self.elements
+            .iter()
+            .filter(|(tag, _)| !is_identifying(*tag))
+            .filter_map(|(tag, value)| {
+                let (_, name) = LABEL_TAGS.iter().find(|(label_tag, _)| label_tag == tag)?;
+                Some(Label::parse(*name, value))
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Encoding {
+    explicit: bool,
+    big_endian: bool,
+}
+
+struct Element {
+    tag: Tag,
+    length: u32,
+}
+
+pub fn read_dicom<R: Read + Seek>(reader: &mut R) -> std::io::Result<DICOM> {
+    let mut header = [0u8; PREAMBLE_LENGTH + 4];
+    reader.read_exact(&mut header)?;
+    if &header[PREAMBLE_LENGTH..] != SIGNATURE {
+        return Err(invalid_data("dicom", "missing DICM signature"));
+    }
+
+    // the file meta information is always explicit little endian
+    let meta = Encoding {
+        explicit: true,
+        big_endian: false,
+    };
+    let mut transfer_syntax = String::new();
+    loop {
+        let position = reader.stream_position()?;
+        let element = read_element(reader, meta)?;
+        if element.tag.0 != 0x0002 {
+            reader.seek(SeekFrom::Start(position))?;
+            break;
+        }
+        if element.tag == TRANSFER_SYNTAX && element.length <= MAX_VALUE_LENGTH {
+            transfer_syntax = text(&read_vec(reader, u64::from(element.length))?);
+        } else {
+            skip(reader, &element, meta, 0)?;
+        }
+    }
+    let encoding = match transfer_syntax.as_str() {
+        IMPLICIT_LITTLE_ENDIAN => Encoding {
+            explicit: false,
+            big_endian: false,
+        },
+        EXPLICIT_BIG_ENDIAN => Encoding {
+            explicit: true,
+            big_endian: true,
+        },
+        DEFLATED => {
+            return Err(invalid_data(
+                "dicom",
+                "deflated data sets are not supported",
+            ))
+        }
+        _ => meta,
+    };
+
+    let mut dicom = DICOM {
+        frames: 1,
+        ..DICOM::default()
+    };
+    loop {
+        let element = match read_element(reader, encoding) {
+            Ok(element) => element,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        // all tags we need come before the pixel data
+        if element.tag == PIXEL_DATA || element.tag > COLUMNS {
+            break;
+        }
+        let wanted = element.tag == ROWS
+            || element.tag == COLUMNS
+            || element.tag == NUMBER_OF_FRAMES
+            || LABEL_TAGS.iter().any(|(tag, _)| *tag == element.tag);
+        if !wanted || is_identifying(element.tag) || element.length > MAX_VALUE_LENGTH {
+            skip(reader, &element, encoding, 0)?;
+            continue;
+        }
+        let value = read_vec(reader, u64::from(element.length))?;
+        match element.tag {
+            ROWS | COLUMNS if value.len() >= 2 => {
+                let number = if encoding.big_endian {
+                    u16::from_be_bytes([value[0], value[1]])
+                } else {
+                    u16::from_le_bytes([value[0], value[1]])
+                };
+                if element.tag == ROWS {
+                    dicom.rows = u32::from(number);
+                } else {
+                    dicom.columns = u32::from(number);
+                }
+            }
+            NUMBER_OF_FRAMES => {
+                dicom.frames = text(&value)
+                    .parse()
+                    .map_err(|_| invalid_data("dicom", "invalid number of frames"))?
+            }
+            tag => {
+                let value = text(&value);
+                if !value.is_empty() {
+                    dicom.elements.push((tag, value));
+                }
+            }
+        }
+    }
+    if dicom.rows == 0 || dicom.columns == 0 || dicom.frames == 0 {
+        return Err(invalid_data("dicom", "missing image size"));
+    }
+    if dicom.frames > MAX_FRAMES {
+        return Err(invalid_data("dicom", "too many frames"));
+    }
+    Ok(dicom)
+}
+
+fn read_element<R: Read>(reader: &mut R, encoding: Encoding) -> std::io::Result<Element> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let u16_at = |offset: usize| {
+        let bytes = [header[offset], header[offset + 1]];
+        if encoding.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    };
+    let u32_of = |bytes: [u8; 4]| {
+        if encoding.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    let tag = Tag(u16_at(0), u16_at(2));
+    // items and delimiters have no value representation
+    if !encoding.explicit || tag.0 == ITEM_GROUP {
+        let length = u32_of([header[4], header[5], header[6], header[7]]);
+        return Ok(Element { tag, length });
+    }
+    let vr = [header[4], header[5]];
+    let length = match &vr {
+        b"OB" | b"OD" | b"OF" | b"OL" | b"OV" | b"OW" | b"SQ" | b"SV" | b"UC" | b"UN" | b"UR"
+        | b"UT" | b"UV" => {
+            let mut length = [0u8; 4];
+            reader.read_exact(&mut length)?;
+            u32_of(length)
+        }
+        _ => u32::from(u16_at(6)),
+    };
+    Ok(Element { tag, length })
+}
+
+/// Skips the value of an element. Sequences and items of undefined length
+/// are walked up to their delimiter.
+fn skip<R: Read + Seek>(
+    reader: &mut R,
+    element: &Element,
+    encoding: Encoding,
+    depth: u32,
+) -> std::io::Result<()> {
+    if element.length != UNDEFINED_LENGTH {
+        reader.seek(SeekFrom::Current(i64::from(element.length)))?;
+        return Ok(());
+    }
+    if depth > MAX_DEPTH {
+        return Err(invalid_data("dicom", "sequences nested too deeply"));
+    }
+    loop {
+        let child = read_element(reader, encoding)?;
+        match child.tag {
+            SEQUENCE_DELIMITER => return Ok(()),
+            // item delimiter, the end of an item of undefined length
+            Tag(ITEM_GROUP, 0xe00d) => {
+                if element.tag.0 != ITEM_GROUP {
+                    return Err(invalid_data("dicom", "unexpected item delimiter"));
+                }
+                return Ok(());
+            }
+            _ => skip(reader, &child, encoding, depth + 1)?,
+        }
+    }
+}
+
+/// Strips the padding of text values.
+fn text(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::image::dicom::{is_identifying, read_dicom, LABEL_TAGS};
+    use crate::image::label::{Label, LabelValue};
+
+    fn element(group: u16, element: u16, vr: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut data = group.to_le_bytes().to_vec();
+        data.extend_from_slice(&element.to_le_bytes());
+        data.extend_from_slice(vr);
+        data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        data.extend_from_slice(value);
+        data
+    }
+
+    #[test]
+    fn reads_size_and_leaves_out_patient_tags() {
+        let mut data = vec![0u8; 128];
+        data.extend_from_slice(b"DICM");
+        data.extend(element(0x0002, 0x0010, b"UI", b"1.2.840.10008.1.2.1\0"));
+        data.extend(element(0x0008, 0x0020, b"DA", b"19870203"));
+        data.extend(element(0x0008, 0x0060, b"CS", b"MR"));
+        data.extend(element(0x0008, 0x0090, b"PN", b"Doe^John"));
+        data.extend(element(0x0008, 0x1030, b"LO", b"Follow-up Jane Roe"));
+        data.extend(element(0x0010, 0x0010, b"PN", b"Roe^Jane"));
+        data.extend(element(0x0028, 0x0008, b"IS", b"3 "));
+        data.extend(element(0x0028, 0x0010, b"US", &512u16.to_le_bytes()));
+        data.extend(element(0x0028, 0x0011, b"US", &256u16.to_le_bytes()));
+        let dicom = read_dicom(&mut Cursor::new(data)).unwrap();
+        assert_eq!((dicom.columns, dicom.rows, dicom.frames), (256, 512, 3));
+        assert_eq!(
+            dicom.labels(),
+            vec![Label::new("Modality", LabelValue::Text("MR".to_owned()))]
+        );
+        assert!(LABEL_TAGS.iter().all(|(tag, _)| !is_identifying(*tag)));
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::Cursor;
+
+use crate::image::label::{Label, LabelValue};
+use crate::image::tiff::{Ifd, TiffReader, Value, TAG_EXIF_IFD, TAG_ORIENTATION};
+use crate::image::Orientation;
+
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+
+// Tags exposed as labels, in IFD0 and the EXIF sub-IFD: text, dates and
+// a few numbers describing the exposure
+const LABEL_TAGS: &[(u16, &str)] = &[
+    (0x010e, "ImageDescription"),
+    (0x010f, "Make"),
+    (0x0110, "Model"),
+    (0x0131, "Software"),
+    (0x0132, "DateTime"),
+    (0x013b, "Artist"),
+    (0x8298, "Copyright"),
+    (0x829a, "ExposureTime"),
+    (0x829d, "FNumber"),
+    (0x8827, "ISOSpeedRatings"),
+    (0x9003, "DateTimeOriginal"),
+    (0x920a, "FocalLength"),
+    (0xa420, "ImageUniqueID"),
+];
+
+/// EXIF metadata embedded in another format: a TIFF structure with the
+/// primary image directory (IFD0) and the optional EXIF sub-directory.
+#[derive(Debug, Default, PartialEq)]
+pub struct Exif {
+    pub primary: Ifd,
+    pub exif: Option<Ifd>,
+}
+
+impl Exif {
+    /// Parses an EXIF block, with or without the `Exif\0\0` prefix used in JPEG.
+    pub fn parse(data: &[u8]) -> std::io::Result<Exif> {
+        let data = if data.starts_with(EXIF_PREFIX) {
+            &data[EXIF_PREFIX.len()..]
+        } else {
+            data
+        };
+        let mut cursor = Cursor::new(data);
+        let mut reader = TiffReader::new(&mut cursor, 0, data.len() as u64)?;
+        let primary = match reader.read_ifds()?.into_iter().next() {
+            Some(ifd) => ifd,
+            None => return Ok(Exif::default()),
+        };
+        let exif = match primary.unsigned(TAG_EXIF_IFD) {
+            Some(offset) => reader.read_ifd(offset).ok().map(|(ifd, _next)| ifd),
+            None => None,
+        };
+        Ok(Exif { primary, exif })
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.primary
+            .unsigned(TAG_ORIENTATION)
+            .map(Orientation::from_exif)
+            .unwrap_or_default()
+    }
+
+    /// Labels for the descriptive tags.
+    pub fn labels(&self) -> Vec<Label> {
+        let mut labels = Vec::new();
+        for ifd in std::iter::once(&self.primary).chain(self.exif.iter()) {
+            for (tag, name) in LABEL_TAGS {
+                if let Some(value) = ifd.get(*tag).and_then(label_value) {
+                    labels.push(Label::new(*name, value));
+                }
+            }
+        }
+        labels
+    }
+}
+
+fn label_value(value: &Value) -> Option<LabelValue> {
+    let typed: Vec<LabelValue> = match value {
+        Value::Ascii(text) if text.is_empty() => return None,
+        Value::Ascii(text) => return Some(LabelValue::parse(text)),
+        Value::Byte(values) => values
+            .iter()
+            .map(|v| LabelValue::Integer(i64::from(*v)))
+            .collect(),
+        Value::Short(values) => values
+            .iter()
+            .map(|v| LabelValue::Integer(i64::from(*v)))
+            .collect(),
+        Value::Long(values) => values
+            .iter()
+            .map(|v| LabelValue::Integer(*v as i64))
+            .collect(),
+        Value::Rational(values) => values
+            .iter()
+            .map(|(n, d)| LabelValue::rational(i64::from(*n), i64::from(*d)))
+            .collect(),
+        Value::SignedRational(values) => values
+            .iter()
+            .map(|(n, d)| LabelValue::rational(i64::from(*n), i64::from(*d)))
+            .collect(),
+        Value::Undefined(_) | Value::Skipped(_) => return None,
+    };
+    match typed.len() {
+        0 => None,
+        1 => typed.into_iter().next(),
+        _ => Some(LabelValue::List(typed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::exif::Exif;
+    use crate::image::label::{Label, LabelValue};
+    use crate::image::Orientation;
+
+    #[test]
+    fn reads_text_tags() {
+        let mut data = b"Exif\0\0MM\0\x2a\0\0\0\x08".to_vec();
+        // four entries: Orientation = 6, Artist = "Jane",
+        // ExposureTime = 10/2500, ISOSpeedRatings = 200
+        data.extend_from_slice(&[0, 4]);
+        data.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        data.extend_from_slice(&[0x01, 0x3b, 0, 2, 0, 0, 0, 5, 0, 0, 0, 62]);
+        data.extend_from_slice(&[0x82, 0x9a, 0, 5, 0, 0, 0, 1, 0, 0, 0, 67]);
+        data.extend_from_slice(&[0x88, 0x27, 0, 3, 0, 0, 0, 1, 0, 200, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(b"Jane\0");
+        data.extend_from_slice(&[0, 0, 0, 10, 0, 0, 0x09, 0xc4]);
+        let exif = Exif::parse(&data).unwrap();
+        assert_eq!(exif.orientation(), Orientation::from_exif(6));
+        assert_eq!(
+            exif.labels(),
+            vec![
+                Label::new("Artist", LabelValue::Text("Jane".to_owned())),
+                Label::new("ExposureTime", LabelValue::Rational(1, 250)),
+                Label::new("ISOSpeedRatings", LabelValue::Integer(200)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use crate::image::invalid_data;
+
+const XMP_APPLICATION: &[u8] = b"XMP DataXMP";
+const XMP_END: &[u8] = b"<?xpacket end=";
+const MAX_XMP_LENGTH: usize = 1024 * 1024;
+
+/// Logical screen size and XMP packet of a GIF file.
+#[derive(Debug, Default, PartialEq)]
+pub struct GIF {
+    pub width: u32,
+    pub height: u32,
+    pub xmp: Option<String>,
+}
+
+impl GIF {
+    pub fn load(path: &PathBuf) -> std::io::Result<GIF> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_gif(&mut file)
+    }
+}
+
+/// Reads the header and walks the blocks to find an XMP application
+/// extension. Image data is skipped sub-block by sub-block, within the
+/// buffer.
+pub fn read_gif<R: Read + Seek>(reader: &mut BufReader<R>) -> std::io::Result<GIF> {
+    let mut header = [0u8; 13];
+    reader.read_exact(&mut header)?;
+    if &header[..6] != b"GIF87a" && &header[..6] != b"GIF89a" {
+        return Err(invalid_data("gif", "missing GIF signature"));
+    }
+    let mut gif = GIF {
+        width: u32::from(u16::from_le_bytes([header[6], header[7]])),
+        height: u32::from(u16::from_le_bytes([header[8], header[9]])),
+        xmp: None,
+    };
+    skip_colour_table(reader, header[10])?;
+
+    loop {
+        let mut introducer = [0u8; 1];
+        // a missing trailer is not worth failing for, we already have the size
+        if reader.read(&mut introducer)? == 0 {
+            break;
+        }
+        match introducer[0] {
+            // extension
+            0x21 => {
+                let mut label = [0u8; 1];
+                reader.read_exact(&mut label)?;
+                if label[0] == 0xff {
+                    let mut identifier = [0u8; 12];
+                    reader.read_exact(&mut identifier)?;
+                    if identifier[0] == 11 && &identifier[1..] == XMP_APPLICATION {
+                        gif.xmp = Some(read_xmp(reader)?);
+                    } else {
+                        reader.seek_relative(-12)?;
+                    }
+                }
+                skip_sub_blocks(reader)?;
+            }
+            // image descriptor
+            0x2c => {
+                let mut descriptor = [0u8; 9];
+                reader.read_exact(&mut descriptor)?;
+                skip_colour_table(reader, descriptor[8])?;
+                let mut _minimum_code_size = [0u8; 1];
+                reader.read_exact(&mut _minimum_code_size)?;
+                skip_sub_blocks(reader)?;
+            }
+            // trailer
+            0x3b => break,
+            _ => return Err(invalid_data("gif", "unknown block")),
+        }
+    }
+    Ok(gif)
+}
+
+fn skip_colour_table<R: Read + Seek>(reader: &mut BufReader<R>, flags: u8) -> std::io::Result<()> {
+    if flags & 0x80 != 0 {
+        let size = 3 * (1i64 << ((flags & 0x07) + 1));
+        reader.seek_relative(size)?;
+    }
+    Ok(())
+}
+
+fn skip_sub_blocks<R: Read + Seek>(reader: &mut BufReader<R>) -> std::io::Result<()> {
+    loop {
+        let mut length = [0u8; 1];
+        reader.read_exact(&mut length)?;
+        if length[0] == 0 {
+            return Ok(());
+        }
+        reader.seek_relative(i64::from(length[0]))?;
+    }
+}
+
+/// XMP is stored raw (not in sub-blocks) and followed by a "magic trailer"
+/// that reads as valid sub-blocks, so it is read up to the end of the packet
+/// and the rest is skipped as sub-blocks.
+fn read_xmp<R: Read>(reader: &mut R) -> std::io::Result<String> {
+    let mut packet = Vec::new();
+    let mut byte = [0u8; 1];
+    let mut in_end_tag = false;
+    while packet.len() < MAX_XMP_LENGTH {
+        reader.read_exact(&mut byte)?;
+        packet.push(byte[0]);
+        if in_end_tag && byte[0] == b'>' {
+            break;
+        }
+        in_end_tag = in_end_tag || packet.ends_with(XMP_END);
+    }
+    Ok(String::from_utf8_lossy(&packet).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor};
+
+    use crate::image::gif::read_gif;
+
+    #[test]
+    fn finds_xmp_after_skipping_blocks() {
+        let mut data = b"GIF89a\x40\x01\xf0\x00\x80\x00\x00".to_vec();
+        // global colour table of two entries
+        data.extend_from_slice(&[0; 6]);
+        // image with two data sub-blocks
+        data.extend_from_slice(b"\x2c\0\0\0\0\x40\x01\xf0\x00\x00\x02");
+        data.extend_from_slice(b"\x03abc\x02de\x00");
+        // XMP application extension, its trailer reads as sub-blocks
+        data.extend_from_slice(b"\x21\xff\x0bXMP DataXMP");
+        data.extend_from_slice(b"<?xpacket begin=''?><x:xmpmeta/><?xpacket end='r'?>");
+        data.extend_from_slice(b"\x02\xff\xfe\x00");
+        data.push(0x3b);
+        let gif = read_gif(&mut BufReader::new(Cursor::new(data))).unwrap();
+        assert_eq!((gif.width, gif.height), (320, 240));
+        assert_eq!(
+            gif.xmp.unwrap(),
+            "<?xpacket begin=''?><x:xmpmeta/><?xpacket end='r'?>"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::image::source::Image;
+
+// Stale records are only dropped when the index is opened, if there are
+// more of them than current ones
+const MIN_STALE_RECORDS: usize = 1000;
+
+/// Images read from files, kept on disk so a restart does not read every
+/// file again. The index is a JSON Lines file: a header, then one record per
+/// file. Changed files get a new record at the end, so updates never
+/// rewrite the file. Only paths and offsets are held in memory.
+pub struct Index {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: File,
+    /// Version and offset of the current record of each file
+    records: HashMap<PathBuf, (String, u64)>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Header {
+    /// Changes when records of the same files would differ, e.g. with
+    /// other integrity options
+    fingerprint: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Record {
+    path: PathBuf,
+    version: String,
+    images: Vec<Image>,
+}
+
+impl Index {
+    /// Opens an index, or creates it if it does not exist or was written
+    /// with another fingerprint.
+    pub fn open(path: &Path, fingerprint: &str) -> std::io::Result<Index> {
+        if !path.exists() {
+            return Index::create(path, fingerprint);
+        }
+        let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+        let mut reader = BufReader::new(&mut file);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match serde_json::from_str::<Header>(&line) {
+            Ok(header) if header.fingerprint == fingerprint => (),
+            _ => {
+                println!("Index {} is outdated, starting a new one", path.display());
+                return Index::create(path, fingerprint);
+            }
+        }
+        let mut records = HashMap::new();
+        let mut offset = line.len() as u64;
+        let mut count = 0;
+        loop {
+            line.clear();
+            let length = reader.read_line(&mut line)?;
+            // a record cut short by a crash is overwritten by the next one
+            if length == 0 || !line.ends_with('\n') {
+                break;
+            }
+            if let Ok(record) = serde_json::from_str::<Record>(&line) {
+                records.insert(record.path, (record.version, offset));
+                count += 1;
+            }
+            offset += length as u64;
+        }
+        file.set_len(offset)?;
+        let index = Index {
+            path: path.to_owned(),
+            state: Mutex::new(State { file, records }),
+        };
+        if count - index.len() > MIN_STALE_RECORDS.max(index.len()) {
+            index.compact(fingerprint)?;
+        }
+        Ok(index)
+    }
+
+    /// Creates an empty index, replacing an existing one.
+    pub fn create(path: &Path, fingerprint: &str) -> std::io::Result<Index> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        write_line(
+            &mut file,
+            &Header {
+                fingerprint: fingerprint.to_owned(),
+            },
+        )?;
+        // appending from now on
+        let file = OpenOptions::new().read(true).append(true).open(path)?;
+        Ok(Index {
+            path: path.to_owned(),
+            state: Mutex::new(State {
+                file,
+                records: HashMap::new(),
+            }),
+        })
+    }
+
+    /// The images of a file if the index has them for this version.
+    pub fn get(&self, path: &Path, version: &str) -> Option<Vec<Image>> {
+        let mut state = self.lock();
+        let offset = match state.records.get(path) {
+            Some((indexed, offset)) if indexed == version => *offset,
+            _ => return None,
+        };
+        let record: Record = read_record(&mut state.file, offset).ok()?;
+        Some(record.images)
+    }
+
+    pub fn insert(&self, path: &Path, version: &str, images: &[Image]) -> std::io::Result<()> {
+        let mut state = self.lock();
+        let offset = state.file.seek(SeekFrom::End(0))?;
+        let record = Record {
+            path: path.to_owned(),
+            version: version.to_owned(),
+            images: images.to_vec(),
+        };
+        write_line(&mut state.file, &record)?;
+        state.records.insert(record.path, (record.version, offset));
+        Ok(())
+    }
+
+    /// Number of files in the index.
+    pub fn len(&self) -> usize {
+        self.lock().records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the current records to a new file that replaces the index.
+    fn compact(&self, fingerprint: &str) -> std::io::Result<()> {
+        let mut state = self.lock();
+        let compacted_path = self.path.with_extension("compacting");
+        let mut compacted = File::create(&compacted_path)?;
+        write_line(
+            &mut compacted,
+            &Header {
+                fingerprint: fingerprint.to_owned(),
+            },
+        )?;
+        let mut offsets: Vec<(PathBuf, u64)> = state
+            .records
+            .iter()
+            .map(|(path, (_, offset))| (path.clone(), *offset))
+            .collect();
+        offsets.sort_by_key(|(_, offset)| *offset);
+        let mut records = HashMap::new();
+        for (_, offset) in offsets {
+            let record: Record = read_record(&mut state.file, offset)?;
+            let new_offset = compacted.seek(SeekFrom::End(0))?;
+            write_line(&mut compacted, &record)?;
+            records.insert(record.path, (record.version, new_offset));
+        }
+        compacted.sync_all()?;
+        std::fs::rename(&compacted_path, &self.path)?;
+        state.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        state.records = records;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn read_record(file: &mut File, offset: u64) -> std::io::Result<Record> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("could not parse index record: {}", e),
+        )
+    })
+}
+
+/// Writes a value as one line of JSON, in a single write so that readers
+/// never see half a record.
+fn write_line<T: Serialize>(file: &mut File, value: &T) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    file.write_all(&line)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::index::Index;
+    use crate::image::label::Label;
+    use crate::image::source::Image;
+    use crate::image::Format;
+    use std::io::Write;
+    use std::path::Path;
+
+    #[test]
+    fn keeps_records_across_restarts() {
+        let path = std::env::temp_dir().join(format!("forager-index-{}.jsonl", std::process::id()));
+        let mut image = Image::new("a.png".to_owned(), Format::PNG, 10, 20);
+        image.labels.push(Label::parse("Title", "Harbour"));
+
+        let index = Index::create(&path, "v1").unwrap();
+        index
+            .insert(Path::new("/a.png"), "1", &[image.clone()])
+            .unwrap();
+        index.insert(Path::new("/b.png"), "1", &[]).unwrap();
+        index.insert(Path::new("/a.png"), "2", &[image]).unwrap();
+        drop(index);
+        // a record cut short
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"path\":\"/c.png\"").unwrap();
+
+        let index = Index::open(&path, "v1").unwrap();
+        assert_eq!(index.len(), 2);
+        assert!(index.get(Path::new("/a.png"), "1").is_none());
+        let images = index.get(Path::new("/a.png"), "2").unwrap();
+        assert_eq!((images[0].width, images[0].height), (10, 20));
+        assert_eq!(images[0].labels, vec![Label::parse("Title", "Harbour")]);
+        index.insert(Path::new("/c.png"), "1", &[]).unwrap();
+        assert_eq!(
+            index.get(Path::new("/c.png"), "1").map(|i| i.len()),
+            Some(0)
+        );
+        drop(index);
+
+        assert_eq!(Index::open(&path, "v1").unwrap().len(), 3);
+        assert!(Index::open(&path, "v2").unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
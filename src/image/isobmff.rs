@@ -0,0 +1,385 @@
+use nom::{
+    bytes::complete::{take, take_till},
+    number::complete::{be_u16, be_u32, be_u64, be_u8},
+    IResult,
+};
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec, Orientation};
+
+// The meta box holds only item descriptions and properties
+const MAX_META_LENGTH: u64 = 16 * 1024 * 1024;
+const MAX_ITEM_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// Primary image of a HEIF file (HEIC, AVIF): size, transformations and
+/// the EXIF and XMP items, read from the `meta` box.
+#[derive(Debug, Default, PartialEq)]
+pub struct HEIF {
+    pub brand: String,
+    pub width: u32,
+    pub height: u32,
+    /// Rotation (irot) and mirroring (imir), applied in the order listed
+    pub orientation: Orientation,
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Item {
+    id: u32,
+    item_type: [u8; 4],
+    content_type: String,
+}
+
+#[derive(Debug, Default)]
+struct Location {
+    item_id: u32,
+    construction_method: u16,
+    // offset and length
+    extents: Vec<(u64, u64)>,
+}
+
+#[derive(Debug)]
+enum Property {
+    Size(u32, u32),
+    Rotation(u16),
+    Mirror(u8),
+    Other,
+}
+
+#[derive(Debug, Default)]
+struct Meta {
+    primary: u32,
+    items: Vec<Item>,
+    locations: Vec<Location>,
+    properties: Vec<Property>,
+    // item id and 1-based property indices
+    associations: Vec<(u32, Vec<u16>)>,
+}
+
+impl HEIF {
+    pub fn load(path: &PathBuf) -> std::io::Result<HEIF> {
+        let mut file = File::open(path)?;
+        read_heif(&mut file)
+    }
+}
+
+pub fn read_heif<R: Read + Seek>(reader: &mut R) -> std::io::Result<HEIF> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut heif = HEIF::default();
+    let mut meta = None;
+    let mut position = 0;
+    while position < file_length && meta.is_none() {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let length = u64::from(u32::from_be_bytes([
+            header[0], header[1], header[2], header[3],
+        ]));
+        let (header_length, box_length) = match length {
+            0 => (8, file_length - position),
+            1 => {
+                let mut extended = [0u8; 8];
+                reader.read_exact(&mut extended)?;
+                (16, u64::from_be_bytes(extended))
+            }
+            length => (8, length),
+        };
+        if box_length < header_length || box_length > file_length - position {
+            return Err(invalid_data("heif", "box length exceeds file"));
+        }
+        let data_length = box_length - header_length;
+        match &header[4..] {
+            b"ftyp" => {
+                let data = read_vec(reader, data_length.min(4))?;
+                heif.brand = String::from_utf8_lossy(&data).into_owned();
+            }
+            b"meta" if data_length <= MAX_META_LENGTH => {
+                let data = read_vec(reader, data_length)?;
+                meta = match parse_meta(&data) {
+                    Ok((_, meta)) => Some(meta),
+                    Err(_) => return Err(invalid_data("heif", "malformed meta box")),
+                };
+            }
+            _ => (),
+        }
+        position += box_length;
+        reader.seek(SeekFrom::Start(position))?;
+    }
+    let meta = meta.ok_or_else(|| invalid_data("heif", "missing meta box"))?;
+
+    let primary_properties = meta
+        .associations
+        .iter()
+        .find(|(item_id, _)| *item_id == meta.primary)
+        .map(|(_, properties)| properties.as_slice())
+        .unwrap_or(&[]);
+    for index in primary_properties {
+        match meta.properties.get(usize::from(*index).wrapping_sub(1)) {
+            Some(Property::Size(width, height)) => {
+                heif.width = *width;
+                heif.height = *height;
+            }
+            // irot is anti-clockwise
+            Some(Property::Rotation(angle)) => {
+                heif.orientation = heif.orientation.rotate(360 - angle % 360)
+            }
+            // axis 0 is vertical (left to right), 1 horizontal (top to bottom)
+            Some(Property::Mirror(0)) => heif.orientation = heif.orientation.mirror(),
+            Some(Property::Mirror(_)) => heif.orientation = heif.orientation.mirror().rotate(180),
+            _ => (),
+        }
+    }
+    if heif.width == 0 || heif.height == 0 {
+        return Err(invalid_data("heif", "missing size of primary item"));
+    }
+
+    for item in meta.items.iter() {
+        let is_exif = &item.item_type == b"Exif";
+        let is_xmp = &item.item_type == b"mime" && item.content_type == "application/rdf+xml";
+        if !is_exif && !is_xmp {
+            continue;
+        }
+        let location = match meta.locations.iter().find(|l| l.item_id == item.id) {
+            Some(location) => location,
+            None => continue,
+        };
+        let data = match read_item(reader, location, file_length)? {
+            Some(data) => data,
+            None => continue,
+        };
+        if is_exif && data.len() > 4 {
+            // the data starts with the offset of the TIFF header
+            let offset = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            if let Some(tiff) = data.get(4 + offset..) {
+                heif.exif = Some(tiff.to_vec());
+            }
+        } else if is_xmp {
+            heif.xmp = Some(String::from_utf8_lossy(&data).into_owned());
+        }
+    }
+    Ok(heif)
+}
+
+/// Reads item data stored in the file (construction method 0).
+fn read_item<R: Read + Seek>(
+    reader: &mut R,
+    location: &Location,
+    file_length: u64,
+) -> std::io::Result<Option<Vec<u8>>> {
+    if location.construction_method != 0 {
+        return Ok(None);
+    }
+    let mut data = Vec::new();
+    for (offset, length) in location.extents.iter() {
+        let end = offset.checked_add(*length);
+        let beyond_file = !matches!(end, Some(end) if end <= file_length);
+        if beyond_file || data.len() as u64 + length > MAX_ITEM_LENGTH {
+            return Ok(None);
+        }
+        reader.seek(SeekFrom::Start(*offset))?;
+        data.extend(read_vec(reader, *length)?);
+    }
+    Ok(Some(data))
+}
+
+/// A box fully contained in `input`, returns type and data.
+fn parse_box(input: &[u8]) -> IResult<&[u8], (&[u8], &[u8])> {
+    let (input, length) = be_u32(input)?;
+    let (input, box_type) = take(4u32)(input)?;
+    let (input, data) = take(length.saturating_sub(8))(input)?;
+    Ok((input, (box_type, data)))
+}
+
+fn parse_full_box_header(input: &[u8]) -> IResult<&[u8], (u8, u32)> {
+    let (input, version) = be_u8(input)?;
+    let (input, flags) = take(3u32)(input)?;
+    let flags = u32::from_be_bytes([0, flags[0], flags[1], flags[2]]);
+    Ok((input, (version, flags)))
+}
+
+fn parse_meta(input: &[u8]) -> IResult<&[u8], Meta> {
+    let (mut input, _) = parse_full_box_header(input)?;
+    let mut meta = Meta::default();
+    while !input.is_empty() {
+        let (rest, (box_type, data)) = parse_box(input)?;
+        input = rest;
+        match box_type {
+            b"pitm" => {
+                let (data, (version, _)) = parse_full_box_header(data)?;
+                meta.primary = parse_id(data, version == 0)?.1;
+            }
+            b"iinf" => meta.items = parse_iinf(data)?.1,
+            b"iloc" => meta.locations = parse_iloc(data)?.1,
+            b"iprp" => parse_iprp(data, &mut meta)?,
+            _ => (),
+        }
+    }
+    Ok((input, meta))
+}
+
+fn parse_id(input: &[u8], short: bool) -> IResult<&[u8], u32> {
+    if short {
+        let (input, id) = be_u16(input)?;
+        Ok((input, u32::from(id)))
+    } else {
+        be_u32(input)
+    }
+}
+
+fn parse_iinf(input: &[u8]) -> IResult<&[u8], Vec<Item>> {
+    let (input, (version, _)) = parse_full_box_header(input)?;
+    let (mut input, _count) = parse_id(input, version == 0)?;
+    let mut items = Vec::new();
+    while !input.is_empty() {
+        let (rest, (box_type, data)) = parse_box(input)?;
+        input = rest;
+        if box_type != b"infe" {
+            continue;
+        }
+        let (data, (version, _)) = parse_full_box_header(data)?;
+        if version < 2 {
+            continue;
+        }
+        let (data, id) = parse_id(data, version == 2)?;
+        let (data, _protection_index) = be_u16(data)?;
+        let (data, item_type) = take(4u32)(data)?;
+        let mut item = Item {
+            id,
+            item_type: [item_type[0], item_type[1], item_type[2], item_type[3]],
+            content_type: String::new(),
+        };
+        if item_type == b"mime" {
+            let (data, _name) = take_till(|b| b == 0)(data)?;
+            let (data, _delim) = take(1u32)(data)?;
+            let (_, content_type) = take_till(|b| b == 0)(data)?;
+            item.content_type = String::from_utf8_lossy(content_type).into_owned();
+        }
+        items.push(item);
+    }
+    Ok((input, items))
+}
+
+fn parse_sized(input: &[u8], size: u8) -> IResult<&[u8], u64> {
+    match size {
+        0 => Ok((input, 0)),
+        4 => {
+            let (input, value) = be_u32(input)?;
+            Ok((input, u64::from(value)))
+        }
+        8 => be_u64(input),
+        _ => Err(nom::Err::Error(nom::error::ParseError::from_error_kind(
+            input,
+            nom::error::ErrorKind::Verify,
+        ))),
+    }
+}
+
+fn parse_iloc(input: &[u8]) -> IResult<&[u8], Vec<Location>> {
+    let (input, (version, _)) = parse_full_box_header(input)?;
+    let (input, sizes) = be_u16(input)?;
+    let offset_size = (sizes >> 12) as u8;
+    let length_size = ((sizes >> 8) & 0x0f) as u8;
+    let base_offset_size = ((sizes >> 4) & 0x0f) as u8;
+    let index_size = if version > 0 { (sizes & 0x0f) as u8 } else { 0 };
+    let (mut input, count) = parse_id(input, version < 2)?;
+    let mut locations = Vec::new();
+    for _ in 0..count {
+        let (rest, item_id) = parse_id(input, version < 2)?;
+        let (rest, construction_method) = if version > 0 {
+            let (rest, value) = be_u16(rest)?;
+            (rest, value & 0x0f)
+        } else {
+            (rest, 0)
+        };
+        let (rest, _data_reference_index) = be_u16(rest)?;
+        let (rest, base_offset) = parse_sized(rest, base_offset_size)?;
+        let (mut rest, extent_count) = be_u16(rest)?;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            let (r, _index) = parse_sized(rest, index_size)?;
+            let (r, offset) = parse_sized(r, offset_size)?;
+            let (r, length) = parse_sized(r, length_size)?;
+            rest = r;
+            extents.push((base_offset.saturating_add(offset), length));
+        }
+        input = rest;
+        locations.push(Location {
+            item_id,
+            construction_method,
+            extents,
+        });
+    }
+    Ok((input, locations))
+}
+
+fn parse_iprp<'a>(
+    input: &'a [u8],
+    meta: &mut Meta,
+) -> Result<(), nom::Err<(&'a [u8], nom::error::ErrorKind)>> {
+    let mut input = input;
+    while !input.is_empty() {
+        let (rest, (box_type, data)) = parse_box(input)?;
+        input = rest;
+        match box_type {
+            b"ipco" => {
+                let mut properties = data;
+                while !properties.is_empty() {
+                    let (rest, (property_type, data)) = parse_box(properties)?;
+                    properties = rest;
+                    meta.properties.push(parse_property(property_type, data)?.1);
+                }
+            }
+            b"ipma" => {
+                let (data, (version, flags)) = parse_full_box_header(data)?;
+                let (mut data, count) = be_u32(data)?;
+                for _ in 0..count {
+                    let (rest, item_id) = parse_id(data, version < 1)?;
+                    let (mut rest, association_count) = be_u8(rest)?;
+                    let mut indices = Vec::new();
+                    for _ in 0..association_count {
+                        // the highest bit marks essential properties
+                        let (r, index) = if flags & 1 != 0 {
+                            let (r, value) = be_u16(rest)?;
+                            (r, value & 0x7fff)
+                        } else {
+                            let (r, value) = be_u8(rest)?;
+                            (r, u16::from(value & 0x7f))
+                        };
+                        rest = r;
+                        indices.push(index);
+                    }
+                    data = rest;
+                    meta.associations.push((item_id, indices));
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn parse_property<'a>(property_type: &[u8], data: &'a [u8]) -> IResult<&'a [u8], Property> {
+    match property_type {
+        b"ispe" => {
+            let (data, _) = parse_full_box_header(data)?;
+            let (data, width) = be_u32(data)?;
+            let (data, height) = be_u32(data)?;
+            Ok((data, Property::Size(width, height)))
+        }
+        b"irot" => {
+            let (data, angle) = be_u8(data)?;
+            Ok((data, Property::Rotation(u16::from(angle & 0x03) * 90)))
+        }
+        b"imir" => {
+            let (data, axis) = be_u8(data)?;
+            Ok((data, Property::Mirror(axis & 0x01)))
+        }
+        _ => Ok((data, Property::Other)),
+    }
+}
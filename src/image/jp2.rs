@@ -0,0 +1,408 @@
+use nom::{
+    bytes::complete::take,
+    number::complete::{be_i8, be_u16, be_u32, be_u8},
+    IResult,
+};
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec, Resolution};
+
+pub const JP2_SIGNATURE: &[u8] = &[0, 0, 0, 12, b'j', b'P', b' ', b' ', 13, 10, 135, 10];
+pub const CODESTREAM_SIGNATURE: &[u8] = &[0xff, 0x4f, 0xff, 0x51];
+
+// Boxes with metadata larger than this are skipped
+const MAX_BOX_LENGTH: u64 = 16 * 1024 * 1024;
+// The main header of a codestream is usually a few hundred bytes
+const MAX_MAIN_HEADER_LENGTH: u64 = 64 * 1024;
+
+const MARKER_SIZ: u16 = 0xff51;
+const MARKER_COD: u16 = 0xff52;
+const MARKER_SOT: u16 = 0xff90;
+
+/// A JP2 or JPX file or a raw J2K codestream. For raw codestreams only
+/// `codestream` is set.
+#[derive(Debug, PartialEq)]
+pub struct JP2 {
+    pub width: u32,
+    pub height: u32,
+    pub header: Option<ImageHeader>,
+    pub colour: Option<Colour>,
+    /// resc and resd
+    pub capture_resolution: Option<Resolution>,
+    pub display_resolution: Option<Resolution>,
+    pub codestream: Codestream,
+    pub xml: Vec<String>,
+    pub uuids: Vec<UuidBox>,
+}
+
+// ihdr
+#[derive(Debug, PartialEq)]
+pub struct ImageHeader {
+    pub height: u32,
+    pub width: u32,
+    pub components: u16,
+    pub bits_per_component: u8,
+    pub compression_type: u8,
+    pub colourspace_unknown: bool,
+    pub intellectual_property: bool,
+}
+
+// colr
+#[derive(Debug, PartialEq)]
+pub enum Colour {
+    Enumerated(u32),
+    Restricted(Vec<u8>),
+    Other(u8),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UuidBox {
+    pub uuid: [u8; 16],
+    pub data: Vec<u8>,
+}
+
+/// Image and tile geometry from the SIZ and COD markers of a codestream.
+#[derive(Debug, Default, PartialEq)]
+pub struct Codestream {
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub components: u16,
+    /// Number of decomposition levels + 1
+    pub resolution_levels: u8,
+}
+
+impl JP2 {
+    pub fn load(path: &PathBuf) -> std::io::Result<JP2> {
+        let mut file = File::open(path)?;
+        read_jp2(&mut file)
+    }
+}
+
+/// Reads the boxes of a JP2 file (or a raw codestream) without loading
+/// the image data: metadata boxes are read, everything else is skipped
+/// with seeks and only the main header of the codestream is parsed.
+pub fn read_jp2<R: Read + Seek>(reader: &mut R) -> std::io::Result<JP2> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut signature = [0u8; 12];
+    reader.read_exact(&mut signature)?;
+    if signature.starts_with(CODESTREAM_SIGNATURE) {
+        reader.seek(SeekFrom::Start(0))?;
+        let codestream = read_codestream(reader, file_length)?;
+        return Ok(JP2 {
+            width: codestream.width,
+            height: codestream.height,
+            header: None,
+            colour: None,
+            capture_resolution: None,
+            display_resolution: None,
+            codestream,
+            xml: Vec::new(),
+            uuids: Vec::new(),
+        });
+    }
+    if signature != JP2_SIGNATURE {
+        return Err(invalid_data("jp2", "missing jp2 signature"));
+    }
+
+    let mut jp2 = JP2 {
+        width: 0,
+        height: 0,
+        header: None,
+        colour: None,
+        capture_resolution: None,
+        display_resolution: None,
+        codestream: Codestream::default(),
+        xml: Vec::new(),
+        uuids: Vec::new(),
+    };
+    let mut codestream = None;
+    let mut position = JP2_SIGNATURE.len() as u64;
+    while position < file_length && codestream.is_none() {
+        let (box_type, header_length, box_length) = read_box_header(reader, position, file_length)?;
+        let data_length = box_length - header_length;
+        match &box_type {
+            b"jp2h" if data_length <= MAX_BOX_LENGTH => {
+                let data = read_vec(reader, data_length)?;
+                parse_header_box(&data, &mut jp2)
+                    .map_err(|_| invalid_data("jp2", "malformed jp2h box"))?;
+            }
+            b"xml " if data_length <= MAX_BOX_LENGTH => {
+                let data = read_vec(reader, data_length)?;
+                jp2.xml.push(String::from_utf8_lossy(&data).into_owned());
+            }
+            b"uuid" if (16..=MAX_BOX_LENGTH).contains(&data_length) => {
+                let data = read_vec(reader, data_length)?;
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(&data[..16]);
+                jp2.uuids.push(UuidBox {
+                    uuid,
+                    data: data[16..].to_vec(),
+                });
+            }
+            b"jp2c" => codestream = Some(read_codestream(reader, data_length)?),
+            _ => (),
+        }
+        position += box_length;
+        reader.seek(SeekFrom::Start(position))?;
+    }
+
+    jp2.codestream = codestream.ok_or_else(|| invalid_data("jp2", "missing codestream"))?;
+    // the codestream is authoritative, ihdr is only a copy
+    jp2.width = jp2.codestream.width;
+    jp2.height = jp2.codestream.height;
+    Ok(jp2)
+}
+
+/// Returns type, header length and total length of the box at `position`.
+fn read_box_header<R: Read>(
+    reader: &mut R,
+    position: u64,
+    file_length: u64,
+) -> std::io::Result<([u8; 4], u64, u64)> {
+    let remaining = file_length - position;
+    if remaining < 8 {
+        return Err(invalid_data("jp2", "truncated box header"));
+    }
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let box_type = [header[4], header[5], header[6], header[7]];
+    let (header_length, box_length) = match length {
+        0 => (8, remaining),
+        1 => {
+            let mut extended = [0u8; 8];
+            reader.read_exact(&mut extended)?;
+            (16, u64::from_be_bytes(extended))
+        }
+        length => (8, u64::from(length)),
+    };
+    if box_length < header_length || box_length > remaining {
+        return Err(invalid_data("jp2", "box length exceeds file"));
+    }
+    Ok((box_type, header_length, box_length))
+}
+
+fn read_codestream<R: Read>(reader: &mut R, length: u64) -> std::io::Result<Codestream> {
+    let mut data = Vec::new();
+    reader
+        .take(length.min(MAX_MAIN_HEADER_LENGTH))
+        .read_to_end(&mut data)?;
+    match parse_main_header(&data) {
+        Ok((_, codestream)) => Ok(codestream),
+        Err(_) => Err(invalid_data("jp2", "malformed codestream main header")),
+    }
+}
+
+/// Parses the boxes inside the jp2h superbox.
+fn parse_header_box<'a>(input: &'a [u8], jp2: &mut JP2) -> IResult<&'a [u8], ()> {
+    let mut input = input;
+    while !input.is_empty() {
+        let (rest, (box_type, data)) = parse_box(input)?;
+        input = rest;
+        match box_type {
+            b"ihdr" => jp2.header = Some(parse_ihdr(data)?.1),
+            b"colr" => jp2.colour = Some(parse_colr(data)?.1),
+            b"res " => {
+                let mut res = data;
+                while !res.is_empty() {
+                    let (rest, (res_type, res_data)) = parse_box(res)?;
+                    res = rest;
+                    let (_, resolution) = parse_resolution(res_data)?;
+                    match res_type {
+                        b"resc" => jp2.capture_resolution = Some(resolution),
+                        b"resd" => jp2.display_resolution = Some(resolution),
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok((input, ()))
+}
+
+/// A box fully contained in `input` (no extended or open ended lengths).
+fn parse_box(input: &[u8]) -> IResult<&[u8], (&[u8], &[u8])> {
+    let (input, length) = be_u32(input)?;
+    let (input, box_type) = take(4u32)(input)?;
+    let (input, data) = take(length.saturating_sub(8))(input)?;
+    Ok((input, (box_type, data)))
+}
+
+fn parse_ihdr(input: &[u8]) -> IResult<&[u8], ImageHeader> {
+    let (input, height) = be_u32(input)?;
+    let (input, width) = be_u32(input)?;
+    let (input, components) = be_u16(input)?;
+    let (input, bits_per_component) = be_u8(input)?;
+    let (input, compression_type) = be_u8(input)?;
+    let (input, colourspace_unknown) = be_u8(input)?;
+    let (input, intellectual_property) = be_u8(input)?;
+    let header = ImageHeader {
+        height,
+        width,
+        components,
+        bits_per_component,
+        compression_type,
+        colourspace_unknown: colourspace_unknown != 0,
+        intellectual_property: intellectual_property != 0,
+    };
+    Ok((input, header))
+}
+
+fn parse_colr(input: &[u8]) -> IResult<&[u8], Colour> {
+    let (input, method) = be_u8(input)?;
+    let (input, _precedence) = be_u8(input)?;
+    let (input, _approximation) = be_u8(input)?;
+    match method {
+        1 => {
+            let (input, colourspace) = be_u32(input)?;
+            Ok((input, Colour::Enumerated(colourspace)))
+        }
+        2 => Ok((&input[input.len()..], Colour::Restricted(input.to_vec()))),
+        method => Ok((input, Colour::Other(method))),
+    }
+}
+
+fn parse_resolution(input: &[u8]) -> IResult<&[u8], Resolution> {
+    let (input, vertical_numerator) = be_u16(input)?;
+    let (input, vertical_denominator) = be_u16(input)?;
+    let (input, horizontal_numerator) = be_u16(input)?;
+    let (input, horizontal_denominator) = be_u16(input)?;
+    let (input, vertical_exponent) = be_i8(input)?;
+    let (input, horizontal_exponent) = be_i8(input)?;
+    let value = |numerator: u16, denominator: u16, exponent: i8| {
+        if denominator == 0 {
+            0.0
+        } else {
+            f64::from(numerator) / f64::from(denominator) * 10f64.powi(i32::from(exponent))
+        }
+    };
+    let resolution = Resolution {
+        vertical: value(vertical_numerator, vertical_denominator, vertical_exponent),
+        horizontal: value(
+            horizontal_numerator,
+            horizontal_denominator,
+            horizontal_exponent,
+        ),
+    };
+    Ok((input, resolution))
+}
+
+/// Parses marker segments of the main header up to the first tile-part (SOT).
+fn parse_main_header(input: &[u8]) -> IResult<&[u8], Codestream> {
+    // SOC has no segment
+    let (mut input, _soc) = be_u16(input)?;
+    let mut codestream = Codestream::default();
+    let mut has_size = false;
+    while !input.is_empty() {
+        let (rest, marker) = be_u16(input)?;
+        if marker == MARKER_SOT {
+            break;
+        }
+        let (rest, length) = be_u16(rest)?;
+        let (rest, segment) = take(length.saturating_sub(2))(rest)?;
+        input = rest;
+        match marker {
+            MARKER_SIZ => {
+                parse_siz(segment, &mut codestream)?;
+                has_size = true;
+            }
+            MARKER_COD => {
+                let (segment, _style) = be_u8(segment)?;
+                let (segment, _progression) = be_u8(segment)?;
+                let (segment, _layers) = be_u16(segment)?;
+                let (segment, _transform) = be_u8(segment)?;
+                let (_, decomposition_levels) = be_u8(segment)?;
+                codestream.resolution_levels = decomposition_levels.saturating_add(1);
+            }
+            _ => (),
+        }
+    }
+    if !has_size {
+        return Err(nom::Err::Error(nom::error::ParseError::from_error_kind(
+            input,
+            nom::error::ErrorKind::Eof,
+        )));
+    }
+    Ok((input, codestream))
+}
+
+fn parse_siz<'a>(input: &'a [u8], codestream: &mut Codestream) -> IResult<&'a [u8], ()> {
+    let (input, _capabilities) = be_u16(input)?;
+    let (input, width) = be_u32(input)?;
+    let (input, height) = be_u32(input)?;
+    let (input, x_offset) = be_u32(input)?;
+    let (input, y_offset) = be_u32(input)?;
+    let (input, tile_width) = be_u32(input)?;
+    let (input, tile_height) = be_u32(input)?;
+    let (input, _tile_x_offset) = be_u32(input)?;
+    let (input, _tile_y_offset) = be_u32(input)?;
+    let (input, components) = be_u16(input)?;
+    codestream.width = width.saturating_sub(x_offset);
+    codestream.height = height.saturating_sub(y_offset);
+    codestream.tile_width = tile_width;
+    codestream.tile_height = tile_height;
+    codestream.components = components;
+    Ok((input, ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::image::jp2::{read_jp2, JP2_SIGNATURE};
+
+    fn bx(box_type: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut result = ((data.len() + 8) as u32).to_be_bytes().to_vec();
+        result.extend_from_slice(box_type);
+        result.extend_from_slice(data);
+        result
+    }
+
+    fn codestream() -> Vec<u8> {
+        let mut data = vec![0xff, 0x4f, 0xff, 0x51, 0, 41, 0, 0];
+        for value in &[1200u32, 800, 0, 0, 512, 512, 0, 0] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&[0, 1, 7, 1, 1]);
+        data.extend_from_slice(&[0xff, 0x52, 0, 12, 0, 0, 0, 1, 0, 5, 4, 4, 0, 1]);
+        data.extend_from_slice(&[0xff, 0x90]);
+        data
+    }
+
+    #[test]
+    fn reads_boxes_and_codestream() {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&800u32.to_be_bytes());
+        ihdr.extend_from_slice(&1200u32.to_be_bytes());
+        ihdr.extend_from_slice(&[0, 1, 7, 7, 0, 0]);
+        let resc = bx(b"resc", &[0, 1, 0, 1, 0, 1, 0, 1, 4, 4]);
+        let mut jp2h = bx(b"ihdr", &ihdr);
+        jp2h.extend(bx(b"colr", &[1, 0, 0, 0, 0, 0, 17]));
+        jp2h.extend(bx(b"res ", &resc));
+
+        let mut data = JP2_SIGNATURE.to_vec();
+        data.extend(bx(b"ftyp", b"jp2 \0\0\0\0jp2 "));
+        data.extend(bx(b"jp2h", &jp2h));
+        data.extend(bx(b"xml ", b"<x/>"));
+        data.extend(bx(b"jp2c", &codestream()));
+
+        let jp2 = read_jp2(&mut Cursor::new(data)).unwrap();
+        assert_eq!((jp2.width, jp2.height), (1200, 800));
+        assert_eq!(jp2.header.unwrap().components, 1);
+        assert_eq!(jp2.capture_resolution.unwrap().horizontal, 10000.0);
+        assert_eq!(jp2.xml, vec!["<x/>".to_owned()]);
+        assert_eq!(jp2.codestream.tile_width, 512);
+        assert_eq!(jp2.codestream.resolution_levels, 6);
+
+        let j2k = read_jp2(&mut Cursor::new(codestream())).unwrap();
+        assert_eq!((j2k.width, j2k.height), (1200, 800));
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec};
+
+const EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+const SOI: u8 = 0xd8;
+const SOS: u8 = 0xda;
+const APP1: u8 = 0xe1;
+
+/// Frame size and APP1 metadata of a JPEG file.
+#[derive(Debug, Default, PartialEq)]
+pub struct JPEG {
+    pub width: u32,
+    pub height: u32,
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<String>,
+}
+
+impl JPEG {
+    pub fn load(path: &PathBuf) -> std::io::Result<JPEG> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_jpeg(&mut file)
+    }
+}
+
+/// Reads marker segments up to the start of the scan data.
+pub fn read_jpeg<R: Read>(reader: &mut R) -> std::io::Result<JPEG> {
+    let mut soi = [0u8; 2];
+    reader.read_exact(&mut soi)?;
+    if soi != [0xff, SOI] {
+        return Err(invalid_data("jpeg", "missing start of image"));
+    }
+    let mut jpeg = JPEG::default();
+    loop {
+        let marker = read_marker(reader)?;
+        match marker {
+            // markers without a segment
+            0x01 | 0xd0..=0xd7 => continue,
+            SOS => break,
+            _ => (),
+        }
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length);
+        if length < 2 {
+            return Err(invalid_data("jpeg", "invalid segment length"));
+        }
+        let data = read_vec(reader, u64::from(length - 2))?;
+        match marker {
+            // start of frame, except DHT (c4), JPG (c8) and DAC (cc)
+            0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                if data.len() < 5 {
+                    return Err(invalid_data("jpeg", "short frame header"));
+                }
+                jpeg.height = u32::from(u16::from_be_bytes([data[1], data[2]]));
+                jpeg.width = u32::from(u16::from_be_bytes([data[3], data[4]]));
+            }
+            APP1 if data.starts_with(EXIF_SIGNATURE) && jpeg.exif.is_none() => {
+                jpeg.exif = Some(data[EXIF_SIGNATURE.len()..].to_vec());
+            }
+            APP1 if data.starts_with(XMP_SIGNATURE) && jpeg.xmp.is_none() => {
+                let packet = &data[XMP_SIGNATURE.len()..];
+                jpeg.xmp = Some(String::from_utf8_lossy(packet).into_owned());
+            }
+            _ => (),
+        }
+    }
+    if jpeg.width == 0 || jpeg.height == 0 {
+        return Err(invalid_data("jpeg", "missing frame header"));
+    }
+    Ok(jpeg)
+}
+
+/// Reads the next marker, skipping fill bytes.
+fn read_marker<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    if byte[0] != 0xff {
+        return Err(invalid_data("jpeg", "expected marker"));
+    }
+    while byte[0] == 0xff {
+        reader.read_exact(&mut byte)?;
+    }
+    Ok(byte[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::image::jpeg::read_jpeg;
+
+    #[test]
+    fn reads_frame_and_exif() {
+        let mut data = vec![0xff, 0xd8, 0xff, 0xe1, 0, 16];
+        data.extend_from_slice(b"Exif\0\0MM\0\x2a\0\0\0\x08");
+        data.extend_from_slice(&[0xff, 0xc0, 0, 11, 8, 0, 30, 0, 40, 1, 1, 0x11, 0]);
+        data.extend_from_slice(&[0xff, 0xda]);
+        let jpeg = read_jpeg(&mut Cursor::new(data)).unwrap();
+        assert_eq!((jpeg.width, jpeg.height), (40, 30));
+        assert_eq!(jpeg.exif.unwrap(), b"MM\0\x2a\0\0\0\x08".to_vec());
+    }
+}
@@ -0,0 +1,212 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec, Orientation};
+
+pub const CONTAINER_SIGNATURE: &[u8] = &[0, 0, 0, 12, b'J', b'X', b'L', b' ', 13, 10, 135, 10];
+pub const CODESTREAM_SIGNATURE: &[u8] = &[0xff, 0x0a];
+
+// The size header and orientation need at most 12 bytes after the signature
+const HEADER_LENGTH: u64 = 64;
+const MAX_METADATA_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// Size and metadata of a JPEG XL image, either a bare codestream or an
+/// ISOBMFF-style container with Exif and XMP boxes.
+#[derive(Debug, Default, PartialEq)]
+pub struct JXL {
+    pub width: u32,
+    pub height: u32,
+    /// From the image metadata; Exif orientation is ignored in JPEG XL
+    pub orientation: Orientation,
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<String>,
+}
+
+impl JXL {
+    pub fn load(path: &PathBuf) -> std::io::Result<JXL> {
+        let mut file = File::open(path)?;
+        read_jxl(&mut file)
+    }
+}
+
+pub fn read_jxl<R: Read + Seek>(reader: &mut R) -> std::io::Result<JXL> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let start = read_vec(reader, HEADER_LENGTH.min(file_length))?;
+    if start.starts_with(CODESTREAM_SIGNATURE) {
+        let (width, height, orientation) = parse_headers(&start[2..])?;
+        return Ok(JXL {
+            width,
+            height,
+            orientation,
+            ..JXL::default()
+        });
+    }
+    if !start.starts_with(CONTAINER_SIGNATURE) {
+        return Err(invalid_data("jpeg xl", "missing JPEG XL signature"));
+    }
+
+    let mut jxl = JXL::default();
+    let mut position = CONTAINER_SIGNATURE.len() as u64;
+    reader.seek(SeekFrom::Start(position))?;
+    while position < file_length {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let length = u64::from(u32::from_be_bytes([
+            header[0], header[1], header[2], header[3],
+        ]));
+        let (header_length, box_length) = match length {
+            0 => (8, file_length - position),
+            1 => {
+                let mut extended = [0u8; 8];
+                reader.read_exact(&mut extended)?;
+                (16, u64::from_be_bytes(extended))
+            }
+            length => (8, length),
+        };
+        if box_length < header_length || box_length > file_length - position {
+            return Err(invalid_data("jpeg xl", "box length exceeds file"));
+        }
+        let data_length = box_length - header_length;
+        match &header[4..] {
+            b"jxlc" if jxl.width == 0 => {
+                let data = read_vec(reader, data_length.min(HEADER_LENGTH))?;
+                if !data.starts_with(CODESTREAM_SIGNATURE) {
+                    return Err(invalid_data("jpeg xl", "missing codestream signature"));
+                }
+                let (width, height, orientation) = parse_headers(&data[2..])?;
+                jxl.width = width;
+                jxl.height = height;
+                jxl.orientation = orientation;
+            }
+            // partial codestreams start with a 4 byte index
+            b"jxlp" if jxl.width == 0 => {
+                let data = read_vec(reader, data_length.min(HEADER_LENGTH))?;
+                if data.len() > 4 && data[4..].starts_with(CODESTREAM_SIGNATURE) {
+                    let (width, height, orientation) = parse_headers(&data[6..])?;
+                    jxl.width = width;
+                    jxl.height = height;
+                    jxl.orientation = orientation;
+                }
+            }
+            // starts with the offset of the TIFF header
+            b"Exif" if data_length > 4 && data_length <= MAX_METADATA_LENGTH => {
+                let data = read_vec(reader, data_length)?;
+                let offset = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                jxl.exif = data.get(4 + offset..).map(|tiff| tiff.to_vec());
+            }
+            b"xml " if data_length <= MAX_METADATA_LENGTH => {
+                let data = read_vec(reader, data_length)?;
+                jxl.xmp = Some(String::from_utf8_lossy(&data).into_owned());
+            }
+            _ => (),
+        }
+        position += box_length;
+        reader.seek(SeekFrom::Start(position))?;
+    }
+    if jxl.width == 0 {
+        return Err(invalid_data("jpeg xl", "missing codestream"));
+    }
+    Ok(jxl)
+}
+
+/// Reads bits least significant first, as used by JPEG XL headers.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: usize) -> std::io::Result<u32> {
+        let mut value = 0u32;
+        for i in 0..count {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or_else(|| invalid_data("jpeg xl", "size header truncated"))?;
+            let bit = (byte >> (self.position % 8)) & 1;
+            value |= u32::from(bit) << i;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    /// U32(1 + u(9), 1 + u(13), 1 + u(18), 1 + u(30))
+    fn dimension(&mut self) -> std::io::Result<u32> {
+        let bits = [9, 13, 18, 30][self.bits(2)? as usize];
+        Ok(self.bits(bits)? + 1)
+    }
+}
+
+/// Parses the SizeHeader that follows the codestream signature and the
+/// orientation from the start of the ImageMetadata after it.
+fn parse_headers(data: &[u8]) -> std::io::Result<(u32, u32, Orientation)> {
+    let mut bits = BitReader { data, position: 0 };
+    let (width, height) = parse_size_header(&mut bits)?;
+    let all_default = bits.bits(1)? == 1;
+    let extra_fields = !all_default && bits.bits(1)? == 1;
+    let orientation = if extra_fields {
+        Orientation::from_exif(u64::from(bits.bits(3)? + 1))
+    } else {
+        Orientation::default()
+    };
+    Ok((width, height, orientation))
+}
+
+fn parse_size_header(bits: &mut BitReader) -> std::io::Result<(u32, u32)> {
+    let small = bits.bits(1)? == 1;
+    let height = if small {
+        (bits.bits(5)? + 1) * 8
+    } else {
+        bits.dimension()?
+    };
+    let ratio = bits.bits(3)?;
+    let (numerator, denominator) = match ratio {
+        1 => (1, 1),
+        2 => (12, 10),
+        3 => (4, 3),
+        4 => (3, 2),
+        5 => (16, 9),
+        6 => (5, 4),
+        7 => (2, 1),
+        _ => (0, 0),
+    };
+    let width = if ratio != 0 {
+        (u64::from(height) * numerator / denominator) as u32
+    } else if small {
+        (bits.bits(5)? + 1) * 8
+    } else {
+        bits.dimension()?
+    };
+    Ok((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::jxl::parse_headers;
+    use crate::image::Orientation;
+
+    #[test]
+    fn size_header() {
+        // small, height (1 + 1) * 8, ratio 7 (2:1), not all default,
+        // extra fields, orientation 1 + 5
+        assert_eq!(
+            parse_headers(&[0b1100_0011, 0b0010_1101]).unwrap(),
+            (32, 16, Orientation::from_exif(6))
+        );
+        // not small, height selector 0: 1 + 99, ratio 0, width selector 0: 1 + 199
+        let mut value: u64 = 0;
+        let mut position = 0;
+        for (bits, field) in &[(1, 0), (2, 0), (9, 99), (3, 0), (2, 0), (9, 199)] {
+            value |= (*field as u64) << position;
+            position += bits;
+        }
+        let bytes = value.to_le_bytes();
+        assert_eq!(
+            parse_headers(&bytes).unwrap(),
+            (200, 100, Orientation::default())
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+
+/// A key with a value embedded in an image file (EXIF, XMP, PNG text, ...).
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Label {
+    pub key: String,
+    pub value: LabelValue,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum LabelValue {
+    Text(String),
+    Timestamp(Timestamp),
+    Integer(i64),
+    /// Numerator and denominator, e.g. an exposure time
+    Rational(i64, i64),
+    Uri(String),
+    /// One text in several languages, each with a translated key
+    Localized(Vec<Translation>),
+    List(Vec<LabelValue>),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Translation {
+    /// Empty if unknown
+    pub language: String,
+    pub key: String,
+    pub value: String,
+}
+
+/// A date with an optional time of day and time zone, as found in EXIF
+/// ("2020:04:21 22:34:18"), XMP ("2020-04-21T22:34:18+02:00") or DICOM.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    /// Hours, minutes and seconds, fractions of seconds are dropped
+    pub time: Option<(u8, u8, u8)>,
+    /// Offset from UTC in minutes
+    pub offset: Option<i16>,
+}
+
+impl Label {
+    pub fn new<S: Into<String>>(key: S, value: LabelValue) -> Label {
+        Label {
+            key: key.into(),
+            value,
+        }
+    }
+
+    /// A label with a value given as text, typed if it is a date or a URL.
+    pub fn parse<S: Into<String>>(key: S, value: &str) -> Label {
+        Label::new(key, LabelValue::parse(value))
+    }
+}
+
+impl LabelValue {
+    pub fn parse(value: &str) -> LabelValue {
+        if let Some(timestamp) = Timestamp::parse(value) {
+            LabelValue::Timestamp(timestamp)
+        } else if is_uri(value) {
+            LabelValue::Uri(value.trim().to_owned())
+        } else {
+            LabelValue::Text(value.to_owned())
+        }
+    }
+
+    /// A rational reduced to lowest terms.
+    pub fn rational(numerator: i64, denominator: i64) -> LabelValue {
+        let divisor = gcd(numerator.abs(), denominator.abs());
+        if divisor > 1 {
+            LabelValue::Rational(numerator / divisor, denominator / divisor)
+        } else {
+            LabelValue::Rational(numerator, denominator)
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn is_uri(value: &str) -> bool {
+    let value = value.trim();
+    (value.starts_with("http://") || value.starts_with("https://"))
+        && !value.contains(char::is_whitespace)
+}
+
+impl Timestamp {
+    pub fn parse(value: &str) -> Option<Timestamp> {
+        let value = value.trim();
+        if !value.is_ascii() || value.len() < 10 {
+            return None;
+        }
+        let number = |part: &str| -> Option<u16> {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            part.parse().ok()
+        };
+        let separator = value.as_bytes()[4];
+        if (separator != b'-' && separator != b':') || value.as_bytes()[7] != separator {
+            return None;
+        }
+        let mut timestamp = Timestamp {
+            year: number(&value[..4])?,
+            month: number(&value[5..7])? as u8,
+            day: number(&value[8..10])? as u8,
+            time: None,
+            offset: None,
+        };
+        if timestamp.month == 0 || timestamp.month > 12 || timestamp.day == 0 || timestamp.day > 31
+        {
+            return None;
+        }
+        let rest = &value[10..];
+        if rest.is_empty() {
+            return Some(timestamp);
+        }
+        if !rest.starts_with(['T', ' '])
+            || rest.len() < 9
+            || &rest[3..4] != ":"
+            || &rest[6..7] != ":"
+        {
+            return None;
+        }
+        let (hours, minutes, seconds) = (
+            number(&rest[1..3])?,
+            number(&rest[4..6])?,
+            number(&rest[7..9])?,
+        );
+        if hours > 23 || minutes > 59 || seconds > 60 {
+            return None;
+        }
+        timestamp.time = Some((hours as u8, minutes as u8, seconds as u8));
+        let mut zone = &rest[9..];
+        if zone.starts_with('.') {
+            zone = zone.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+        }
+        timestamp.offset = match zone {
+            "" => None,
+            "Z" => Some(0),
+            _ if zone.len() == 6 && zone.starts_with(['+', '-']) && &zone[3..4] == ":" => {
+                let offset = (number(&zone[1..3])? * 60 + number(&zone[4..])?) as i16;
+                Some(if zone.starts_with('-') {
+                    -offset
+                } else {
+                    offset
+                })
+            }
+            _ => return None,
+        };
+        Some(timestamp)
+    }
+
+    /// The timestamp as required for navDate: a full date and time with a
+    /// time zone. Dates are taken as midnight, times without zone as UTC.
+    pub fn nav_date(&self) -> String {
+        let with_time = Timestamp {
+            time: Some(self.time.unwrap_or((0, 0, 0))),
+            offset: Some(self.offset.unwrap_or(0)),
+            ..*self
+        };
+        with_time.to_string()
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    /// ISO 8601, e.g. "2020-04-21" or "2020-04-21T22:34:18+02:00"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)?;
+        if let Some((hours, minutes, seconds)) = self.time {
+            write!(f, "T{:02}:{:02}:{:02}", hours, minutes, seconds)?;
+            match self.offset {
+                Some(0) => write!(f, "Z")?,
+                Some(offset) => {
+                    let sign = if offset < 0 { '-' } else { '+' };
+                    let offset = offset.abs();
+                    write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)?
+                }
+                None => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for LabelValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelValue::Text(text) | LabelValue::Uri(text) => write!(f, "{}", text),
+            LabelValue::Timestamp(timestamp) => write!(f, "{}", timestamp),
+            LabelValue::Integer(value) => write!(f, "{}", value),
+            LabelValue::Rational(numerator, 0) => write!(f, "{}/0", numerator),
+            LabelValue::Rational(numerator, 1) => write!(f, "{}", numerator),
+            // fractions of a unit, e.g. exposure times
+            LabelValue::Rational(1, denominator) => write!(f, "1/{}", denominator),
+            LabelValue::Rational(numerator, denominator) => {
+                let value = format!("{:.3}", *numerator as f64 / *denominator as f64);
+                write!(f, "{}", value.trim_end_matches('0').trim_end_matches('.'))
+            }
+            LabelValue::Localized(translations) => {
+                for (index, translation) in translations.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "[{}] {}", translation.language, translation.value)?;
+                }
+                Ok(())
+            }
+            LabelValue::List(values) => {
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::label::{LabelValue, Timestamp};
+
+    #[test]
+    fn timestamps() {
+        let exif = Timestamp::parse("2020:04:21 22:34:18").unwrap();
+        assert_eq!(exif.to_string(), "2020-04-21T22:34:18");
+        assert_eq!(exif.nav_date(), "2020-04-21T22:34:18Z");
+        let xmp = Timestamp::parse("2020-04-21T22:34:18.25-05:30").unwrap();
+        assert_eq!(xmp.to_string(), "2020-04-21T22:34:18-05:30");
+        let date = Timestamp::parse("1987-02-03").unwrap();
+        assert_eq!(date.to_string(), "1987-02-03");
+        assert_eq!(date.nav_date(), "1987-02-03T00:00:00Z");
+        assert_eq!(Timestamp::parse("2020:13:01 00:00:00"), None);
+        assert_eq!(Timestamp::parse("21.04.2020"), None);
+        assert_eq!(Timestamp::parse("2020:04:21 22:34"), None);
+    }
+
+    #[test]
+    fn rendering() {
+        assert_eq!(LabelValue::rational(2, 500).to_string(), "1/250");
+        assert_eq!(LabelValue::rational(28, 10).to_string(), "2.8");
+        assert_eq!(LabelValue::rational(50, 1).to_string(), "50");
+        assert_eq!(LabelValue::rational(10, 3).to_string(), "3.333");
+        assert_eq!(
+            LabelValue::parse(" https://creativecommons.org/licenses/by/4.0/"),
+            LabelValue::Uri("https://creativecommons.org/licenses/by/4.0/".to_owned())
+        );
+        assert_eq!(
+            LabelValue::parse("see https://example.org"),
+            LabelValue::Text("see https://example.org".to_owned())
+        );
+        let list = LabelValue::List(vec![LabelValue::Integer(100), LabelValue::rational(1, 60)]);
+        assert_eq!(list.to_string(), "100, 1/60");
+    }
+}
@@ -1,28 +1,269 @@
+pub mod bmp;
+pub mod dicom;
+pub mod exif;
+pub mod gif;
+pub mod index;
+pub mod isobmff;
+pub mod jp2;
+pub mod jpeg;
+pub mod jxl;
+pub mod label;
 pub mod metadata;
-mod png;
+pub mod png;
 pub mod source;
+pub mod svg;
+pub mod tiff;
+pub mod webp;
+pub mod xmp;
 
-#[derive(Debug, PartialEq)]
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum Format {
     PNG,
     JPEG,
     TIFF,
+    JP2,
+    JPX,
+    J2K,
+    WEBP,
+    AVIF,
+    HEIC,
+    HEIF,
+    JXL,
+    GIF,
+    BMP,
+    SVG,
+    DICOM,
+    MP3,
+    WAV,
+    MP4,
+    WEBM,
 }
 
+// Enough to recognize all supported formats, DICOM has a 128 byte preamble
+const MAGIC_LENGTH: usize = dicom::PREAMBLE_LENGTH + 4;
+
 impl Format {
+    /// Detects the format from the first bytes of a file.
+    pub fn sniff(header: &[u8]) -> Option<Format> {
+        if header.starts_with(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]) {
+            Some(Format::PNG)
+        } else if header.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Format::JPEG)
+        } else if header.starts_with(b"II*\0")
+            || header.starts_with(b"MM\0*")
+            || header.starts_with(b"II+\0")
+            || header.starts_with(b"MM\0+")
+        {
+            Some(Format::TIFF)
+        } else if header.starts_with(jp2::JP2_SIGNATURE) {
+            // the brand of the file type box follows the signature
+            match header.get(20..24) {
+                Some(b"jpx ") => Some(Format::JPX),
+                _ => Some(Format::JP2),
+            }
+        } else if header.starts_with(jp2::CODESTREAM_SIGNATURE) {
+            Some(Format::J2K)
+        } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+            Some(Format::WEBP)
+        } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WAVE") {
+            Some(Format::WAV)
+        } else if header.get(4..8) == Some(b"ftyp") {
+            Format::sniff_heif(header).or_else(|| Format::sniff_mp4(header))
+        } else if header.starts_with(jxl::CODESTREAM_SIGNATURE)
+            || header.starts_with(jxl::CONTAINER_SIGNATURE)
+        {
+            Some(Format::JXL)
+        } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+            Some(Format::GIF)
+        } else if Format::is_bmp(header) {
+            Some(Format::BMP)
+        } else if header.get(dicom::PREAMBLE_LENGTH..) == Some(dicom::SIGNATURE) {
+            Some(Format::DICOM)
+        } else if Format::is_svg(header) {
+            Some(Format::SVG)
+        } else if header.starts_with(crate::av::webm::EBML_SIGNATURE) {
+            Some(Format::WEBM)
+        } else if header.starts_with(b"ID3") || Format::is_mpeg_audio_frame(header) {
+            Some(Format::MP3)
+        } else {
+            None
+        }
+    }
+
+    /// Looks at the major and compatible brands of an ISOBMFF file type box.
+    fn sniff_heif(header: &[u8]) -> Option<Format> {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let brands: Vec<&[u8]> = header.get(8..length.min(header.len()))?.chunks(4).collect();
+        let has = |wanted: &[&[u8]]| brands.iter().any(|brand| wanted.contains(brand));
+        if has(&[b"avif", b"avis"]) {
+            Some(Format::AVIF)
+        } else if has(&[b"heic", b"heix", b"heim", b"heis"]) {
+            Some(Format::HEIC)
+        } else if has(&[b"mif1", b"msf1"]) {
+            Some(Format::HEIF)
+        } else {
+            None
+        }
+    }
+
+    /// "BM" alone starts too many other files, so the DIB header size after
+    /// the 14 byte file header has to be a known one, too.
+    fn is_bmp(header: &[u8]) -> bool {
+        match header.get(14..18) {
+            Some(&[a, b, c, d]) if header.starts_with(b"BM") => {
+                bmp::DIB_HEADER_SIZES.contains(&u32::from_le_bytes([a, b, c, d]))
+            }
+            _ => false,
+        }
+    }
+
+    /// Only documents starting with the svg element or doctype are
+    /// recognized, others (e.g. with an XML declaration) go by extension.
+    fn is_svg(header: &[u8]) -> bool {
+        let text = header.strip_prefix(b"\xef\xbb\xbf").unwrap_or(header);
+        let start = text
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .unwrap_or(text.len());
+        text[start..].starts_with(b"<svg") || text[start..].starts_with(b"<!DOCTYPE svg")
+    }
+
+    fn sniff_mp4(header: &[u8]) -> Option<Format> {
+        match header.get(8..12)? {
+            b"isom" | b"iso2" | b"iso4" | b"iso5" | b"iso6" | b"mp41" | b"mp42" | b"avc1"
+            | b"M4A " | b"M4B " | b"M4V " | b"dash" => Some(Format::MP4),
+            _ => None,
+        }
+    }
+
+    /// Sync word, a valid version and layer and a bit rate that is not free
+    /// or invalid.
+    fn is_mpeg_audio_frame(header: &[u8]) -> bool {
+        match header {
+            [0xff, second, third, ..] => {
+                second & 0xe0 == 0xe0
+                    && second & 0x18 != 0x08
+                    && second & 0x06 != 0
+                    && !matches!(third >> 4, 0 | 15)
+            }
+            _ => false,
+        }
+    }
+
+    /// Guesses the format from a file extension, ignoring case.
+    pub fn from_extension(extension: &str) -> Option<Format> {
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Some(Format::PNG),
+            "jpg" | "jpeg" => Some(Format::JPEG),
+            "tif" | "tiff" => Some(Format::TIFF),
+            "jp2" => Some(Format::JP2),
+            "jpx" | "jpf" => Some(Format::JPX),
+            "j2k" | "j2c" => Some(Format::J2K),
+            "webp" => Some(Format::WEBP),
+            "avif" => Some(Format::AVIF),
+            "heic" => Some(Format::HEIC),
+            "heif" => Some(Format::HEIF),
+            "jxl" => Some(Format::JXL),
+            "gif" => Some(Format::GIF),
+            "bmp" => Some(Format::BMP),
+            "svg" => Some(Format::SVG),
+            "dcm" | "dicom" => Some(Format::DICOM),
+            "mp3" => Some(Format::MP3),
+            "wav" => Some(Format::WAV),
+            "mp4" | "m4a" | "m4v" => Some(Format::MP4),
+            "webm" => Some(Format::WEBM),
+            _ => None,
+        }
+    }
+
+    /// Detects the format of a file by its content. The extension is only
+    /// used if the content is not recognized; if both disagree, the content
+    /// wins and a warning is printed.
+    pub fn detect(path: &Path) -> std::io::Result<Option<Format>> {
+        let mut header = Vec::with_capacity(MAGIC_LENGTH);
+        File::open(path)?
+            .take(MAGIC_LENGTH as u64)
+            .read_to_end(&mut header)?;
+        let by_content = Format::sniff(&header);
+        let by_extension = path
+            .extension()
+            .and_then(OsStr::to_str)
+            .and_then(Format::from_extension);
+        match (by_content, by_extension) {
+            (Some(content), Some(extension)) if content != extension => {
+                println!(
+                    "Warning: {} has the extension of {} but contains {}",
+                    path.display(),
+                    extension,
+                    content
+                );
+                Ok(Some(content))
+            }
+            (Some(content), _) => Ok(Some(content)),
+            (None, extension) => Ok(extension),
+        }
+    }
+
     pub fn extension(&self) -> &str {
         match self {
             Format::PNG => "png",
             Format::JPEG => "jpg",
             Format::TIFF => "tif",
+            Format::JP2 => "jp2",
+            Format::JPX => "jpx",
+            Format::J2K => "j2k",
+            Format::WEBP => "webp",
+            Format::AVIF => "avif",
+            Format::HEIC => "heic",
+            Format::HEIF => "heif",
+            Format::JXL => "jxl",
+            Format::GIF => "gif",
+            Format::BMP => "bmp",
+            Format::SVG => "svg",
+            Format::DICOM => "dcm",
+            Format::MP3 => "mp3",
+            Format::WAV => "wav",
+            Format::MP4 => "mp4",
+            Format::WEBM => "webm",
         }
     }
 
+    /// Vector images and time-based media are served by forager itself,
+    /// not by an image server.
+    pub fn has_image_service(&self) -> bool {
+        !matches!(
+            self,
+            Format::SVG | Format::MP3 | Format::WAV | Format::MP4 | Format::WEBM
+        )
+    }
+
     pub fn media_type(&self) -> &str {
         match self {
             Format::PNG => "image/png",
             Format::JPEG => "image/jpeg",
             Format::TIFF => "image/tiff",
+            Format::JP2 => "image/jp2",
+            Format::JPX => "image/jpx",
+            Format::J2K => "image/j2c",
+            Format::WEBP => "image/webp",
+            Format::AVIF => "image/avif",
+            Format::HEIC => "image/heic",
+            Format::HEIF => "image/heif",
+            Format::JXL => "image/jxl",
+            Format::GIF => "image/gif",
+            Format::BMP => "image/bmp",
+            Format::SVG => "image/svg+xml",
+            Format::DICOM => "application/dicom",
+            Format::MP3 => "audio/mpeg",
+            Format::WAV => "audio/wav",
+            Format::MP4 => "video/mp4",
+            Format::WEBM => "video/webm",
         }
     }
 }
@@ -33,15 +274,227 @@ impl std::fmt::Display for Format {
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Label {
-    KV(String, String),
+/// What a canvas shows, as Presentation API content resource type.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Kind {
+    Image,
+    Sound,
+    Video,
+}
+
+/// An error for malformed input, e.g. "could not parse png: missing image
+/// header".
+pub(crate) fn invalid_data(format: &str, message: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("could not parse {}: {}", format, message),
+    )
+}
+
+/// Reads `length` bytes, failing at the end of the input. The buffer grows
+/// with the data read, so a bogus length cannot trigger a huge allocation.
+pub(crate) fn read_vec<R: Read>(reader: &mut R, length: u64) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(length).read_to_end(&mut data)?;
+    if (data.len() as u64) < length {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "unexpected end of file",
+        ));
+    }
+    Ok(data)
+}
+
+/// Position of an image in a file with several pages or frames.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Page {
+    /// Starts at 0
+    pub index: u32,
+    pub count: u32,
+}
+
+/// How stored pixels are transformed for display: mirrored left to right
+/// first if `mirrored` is set, then rotated clockwise by `rotation` degrees.
+/// This is the order of the IIIF Image API rotation parameter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Orientation {
+    pub mirrored: bool,
+    pub rotation: u16,
 }
 
-impl std::fmt::Display for Label {
+impl Orientation {
+    /// Converts the value of the EXIF and TIFF Orientation tag. Unknown
+    /// values are treated as 1 (no transformation).
+    pub fn from_exif(value: u64) -> Orientation {
+        let (mirrored, rotation) = match value {
+            2 => (true, 0),
+            3 => (false, 180),
+            4 => (true, 180),
+            5 => (true, 270),
+            6 => (false, 90),
+            7 => (true, 90),
+            8 => (false, 270),
+            _ => (false, 0),
+        };
+        Orientation { mirrored, rotation }
+    }
+
+    /// Applies a further clockwise rotation, a multiple of 90 degrees.
+    pub fn rotate(self, degrees: u16) -> Orientation {
+        Orientation {
+            mirrored: self.mirrored,
+            rotation: (self.rotation + degrees % 360) % 360,
+        }
+    }
+
+    /// Applies a further left to right mirroring.
+    pub fn mirror(self) -> Orientation {
+        Orientation {
+            mirrored: !self.mirrored,
+            rotation: (360 - self.rotation) % 360,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        !self.mirrored && self.rotation == 0
+    }
+
+    /// True if width and height of the displayed image are swapped.
+    pub fn swaps_dimensions(&self) -> bool {
+        self.rotation % 180 == 90
+    }
+}
+
+impl std::fmt::Display for Orientation {
+    /// Formats as IIIF Image API rotation, e.g. `90` or `!180`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.mirrored {
+            write!(f, "!")?;
+        }
+        write!(f, "{}", self.rotation)
+    }
+}
+
+/// Physical resolution, in pixels per metre.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Resolution {
+    pub vertical: f64,
+    pub horizontal: f64,
+}
+
+impl Resolution {
+    pub fn from_dpi(dpi: f64) -> Resolution {
+        let per_metre = dpi / 0.0254;
+        Resolution {
+            vertical: per_metre,
+            horizontal: per_metre,
+        }
+    }
+
+    /// Width of a pixel in millimetres, None without a usable resolution.
+    pub fn millimetres_per_pixel(&self) -> Option<f64> {
+        if self.horizontal.is_finite() && self.horizontal > 0.0 {
+            Some(1000.0 / self.horizontal)
+        } else {
+            None
+        }
+    }
+}
+
+/// Tile geometry of formats that store images in tiles and resolution levels.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Tiling {
+    pub width: u32,
+    pub height: u32,
+    pub levels: u8,
+}
+
+/// Outcome of checking a file for damage while reading it.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Integrity {
+    /// The format has no checksums or the checks were not run
+    Unchecked,
+    Intact,
+    Damaged(Vec<Defect>),
+}
+
+impl Integrity {
+    pub fn from_defects(defects: Vec<Defect>) -> Integrity {
+        if defects.is_empty() {
+            Integrity::Intact
+        } else {
+            Integrity::Damaged(defects)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Defect {
+    /// Stored and computed checksum of a chunk differ
+    ChecksumMismatch(String),
+    /// A chunk has a valid frame but its content cannot be decoded
+    Malformed(String),
+    /// The file ends before its end marker
+    Truncated,
+    /// Number of bytes found after the end marker
+    TrailingData(usize),
+}
+
+impl std::fmt::Display for Defect {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Label::KV(key, value) => write!(f, "{}: {}", key, value),
+            Defect::ChecksumMismatch(chunk) => write!(f, "checksum mismatch in {} chunk", chunk),
+            Defect::Malformed(chunk) => write!(f, "malformed {} chunk", chunk),
+            Defect::Truncated => write!(f, "file is truncated"),
+            Defect::TrailingData(bytes) => write!(f, "{} bytes after end of file", bytes),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::image::{Format, Orientation};
+
+    #[test]
+    fn sniff_and_extension() {
+        let png = std::fs::read("sample/watergate/simple/MOV_0646000.png").unwrap();
+        assert_eq!(Format::sniff(&png[..16]), Some(Format::PNG));
+        assert_eq!(Format::sniff(b"MM\0*\0\0\0\x08"), Some(Format::TIFF));
+        assert_eq!(Format::sniff(&[0xff, 0x4f, 0xff, 0x51]), Some(Format::J2K));
+        assert_eq!(
+            Format::sniff(b"\0\0\0\x18ftypmif1\0\0\0\0mif1heic"),
+            Some(Format::HEIC)
+        );
+        assert_eq!(Format::sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(Format::WEBP));
+        assert_eq!(Format::sniff(&[0xff, 0xfb, 0x90, 0x00]), Some(Format::MP3));
+        assert_eq!(
+            Format::sniff(b"\0\0\0\x18ftypmp42\0\0\0\0"),
+            Some(Format::MP4)
+        );
+        assert_eq!(Format::sniff(b"\n<svg xmlns="), Some(Format::SVG));
+        assert_eq!(Format::sniff(b"plain text"), None);
+        let bmp = b"BM\x46\0\0\0\0\0\0\0\x36\0\0\0\x28\0\0\0";
+        assert_eq!(Format::sniff(bmp), Some(Format::BMP));
+        assert_eq!(Format::sniff(b"BMW owners club newsletter"), None);
+        assert_eq!(Format::from_extension("JPEG"), Some(Format::JPEG));
+        assert_eq!(Format::from_extension("txt"), None);
+    }
+
+    #[test]
+    fn orientation() {
+        let portrait = Orientation::from_exif(6);
+        assert!(portrait.swaps_dimensions());
+        assert_eq!(portrait.to_string(), "90");
+        assert_eq!(Orientation::from_exif(5).to_string(), "!270");
+        // flipping top to bottom is mirroring and turning upside down
+        assert_eq!(
+            Orientation::default().mirror().rotate(180),
+            Orientation::from_exif(4)
+        );
+        assert_eq!(
+            Orientation::from_exif(8).mirror(),
+            Orientation::from_exif(7)
+        );
+        assert!(Orientation::from_exif(0).is_identity());
+    }
+}
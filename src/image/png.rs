@@ -1,38 +1,51 @@
 extern crate nom;
 
 use nom::{
-    bytes::complete::{tag, take, take_till},
-    multi::many0,
+    bytes::complete::{take, take_till},
     number::complete::{be_u32, be_u8},
     IResult,
 };
 
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::SeekFrom;
 use std::path::PathBuf;
 
+use crate::image::{invalid_data, Defect};
+
 const PNG_SIGNATURE: &[u8] = &[137, 80, 78, 71, 13, 10, 26, 10];
 const ONE: u32 = 1 as u32;
+// length, type and crc
+const CHUNK_FRAME: u64 = 12;
+// Larger chunks are only checksummed, never loaded into memory
+const MAX_DECODED_LENGTH: u32 = 16 * 1024 * 1024;
 
 #[derive(Debug, PartialEq)]
 pub struct PNG {
     pub width: u32,
     pub height: u32,
     pub chunks: Vec<Chunk>,
+    pub defects: Vec<Defect>,
+}
+
+/// Controls how much of a file is read. Chunk headers are always parsed
+/// one by one and image data (IDAT) is skipped with seeks unless it has to
+/// be checksummed, so I/O stays small even for very large files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadOptions {
+    /// Verify checksums and walk the chunk list up to IEND to detect
+    /// truncation and trailing data.
+    pub check_integrity: bool,
+    /// Also read IDAT chunks to verify their checksums (reads the whole file).
+    pub verify_image_data: bool,
+    /// Read chunks after the first IDAT (text chunks may be stored there).
+    pub text_after_data: bool,
 }
 
 impl PNG {
-    pub fn load(path: &PathBuf) -> Result<PNG, std::io::Error> {
-        let mut data = Vec::new();
-        let mut file = File::open(path).unwrap();
-        file.read_to_end(&mut data).unwrap();
-        match parse_png(&data) {
-            Ok((_, png)) => Ok(png),
-            Err(e) => Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("could not parse png: {}", e),
-            )),
-        }
+    pub fn load(path: &PathBuf, options: &ReadOptions) -> Result<PNG, std::io::Error> {
+        let mut file = File::open(path)?;
+        read_png(&mut file, options)
     }
 }
 
@@ -42,7 +55,7 @@ pub enum Chunk {
     ImageHeader(ImageHeader, u32),
     // PLTE
     // IEND
-    End,
+    End(u32),
     // tRNS
     // cHRM
     // gAMA
@@ -57,11 +70,14 @@ pub enum Chunk {
     // zTXt
     // bKGD
     // hIST
-    // pHYs
+    // pHYs - pixels per unit, x and y, unit (1 = metre)
+    PhysicalDimensions(u32, u32, u8, u32),
     // sPLT
     // tIME
-    // All chunks we don't know or support yet
-    Other(String, Vec<u8>, u32),
+    // eXIf
+    Exif(Vec<u8>, u32),
+    // All chunks we don't know or support yet, including IDAT. Their data is not kept.
+    Other(String, u32),
 }
 
 #[derive(Debug, PartialEq)]
@@ -85,111 +101,212 @@ pub struct InternationalText {
     pub text: String,
 }
 
-pub fn parse_png(input: &[u8]) -> IResult<&[u8], PNG> {
-    let (input, _signature) = tag(PNG_SIGNATURE)(input)?;
-    let (input, chunks) = many0(parse_chunk)(input)?;
+/// Reads a PNG chunk by chunk. Damage that still allows reading the image
+/// header (checksum mismatches, malformed chunks, truncation and data after
+/// IEND) is collected in `PNG::defects` instead of failing.
+pub fn read_png<R: Read + Seek>(reader: &mut R, options: &ReadOptions) -> std::io::Result<PNG> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut signature = [0u8; 8];
+    reader.read_exact(&mut signature)?;
+    if signature != PNG_SIGNATURE {
+        return Err(invalid_data("png", "missing png signature"));
+    }
+
+    let mut position = PNG_SIGNATURE.len() as u64;
+    let mut chunks = Vec::new();
+    let mut defects = Vec::new();
+    let mut seen_data = false;
+    loop {
+        if file_length - position < CHUNK_FRAME {
+            defects.push(Defect::Truncated);
+            break;
+        }
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let chunk_type = [header[4], header[5], header[6], header[7]];
+        let type_name = String::from_utf8_lossy(&chunk_type).into_owned();
+        if u64::from(length) + CHUNK_FRAME > file_length - position {
+            defects.push(Defect::Truncated);
+            break;
+        }
+        position += u64::from(length) + CHUNK_FRAME;
+
+        let is_data = &chunk_type == b"IDAT";
+        if is_data && !seen_data {
+            seen_data = true;
+            if !options.check_integrity && !options.text_after_data {
+                break;
+            }
+        }
+
+        if is_data || !is_decoded(&chunk_type) || length > MAX_DECODED_LENGTH {
+            let verify = options.check_integrity && (!is_data || options.verify_image_data);
+            let computed = if verify {
+                Some(hash_through(reader, &chunk_type, length)?)
+            } else {
+                reader.seek(SeekFrom::Current(i64::from(length)))?;
+                None
+            };
+            let crc = read_u32(reader)?;
+            if computed.is_some() && computed != Some(crc) {
+                defects.push(Defect::ChecksumMismatch(type_name.clone()));
+            }
+            chunks.push(Chunk::Other(type_name, crc));
+            continue;
+        }
+
+        // length is bounded by the file size and MAX_DECODED_LENGTH
+        let mut data = vec![0u8; length as usize];
+        reader.read_exact(&mut data)?;
+        let crc = read_u32(reader)?;
+        if options.check_integrity && crc32(&chunk_type, &data) != crc {
+            defects.push(Defect::ChecksumMismatch(type_name.clone()));
+        }
+        let chunk = match parse_chunk_data(&chunk_type, &data, crc) {
+            Ok(chunk) => chunk,
+            Err(_) => {
+                defects.push(Defect::Malformed(type_name.clone()));
+                Chunk::Other(type_name, crc)
+            }
+        };
+        let is_end = chunk == Chunk::End(crc);
+        chunks.push(chunk);
+        if is_end {
+            if position < file_length {
+                defects.push(Defect::TrailingData((file_length - position) as usize));
+            }
+            break;
+        }
+    }
+
     let image_header = chunks.iter().find_map(|chunk| match chunk {
         Chunk::ImageHeader(image_header, _crc) => Some(image_header),
         _ => None,
     });
     match image_header {
-        Some(header) => Ok((
-            input,
-            PNG {
-                width: header.width,
-                height: header.height,
-                chunks,
-            },
-        )),
-        None => {
-            return Err(nom::Err::Error(nom::error::ParseError::from_error_kind(
-                input,
-                nom::error::ErrorKind::Eof,
-            )));
-        }
+        Some(header) => Ok(PNG {
+            width: header.width,
+            height: header.height,
+            chunks,
+            defects,
+        }),
+        None => Err(invalid_data("png", "missing image header")),
     }
 }
 
-fn parse_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
-    let (input, length) = be_u32(input)?;
-    let (input, chunk_type) = take_str(input, 4)?;
-    match chunk_type {
-        "IHDR" => parse_image_header_chunk(input),
-        "tEXt" => parse_text_chunk(input, length),
-        "iTXt" => parse_international_text_chunk(input, length),
-        "gAMA" => parse_image_gamma_chunk(input),
-        "IEND" => parse_end_chunk(input),
-        _ => parse_other_chunk(input, chunk_type, length),
+/// Chunks whose data is read and decoded; all others are skipped or
+/// only checksummed.
+fn is_decoded(chunk_type: &[u8; 4]) -> bool {
+    matches!(
+        chunk_type,
+        b"IHDR" | b"tEXt" | b"iTXt" | b"gAMA" | b"pHYs" | b"eXIf" | b"IEND"
+    )
+}
+
+/// The CRC covers the chunk type and data, but not the length.
+fn crc32(chunk_type: &[u8], data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Computes the CRC of a chunk while reading its data in blocks.
+fn hash_through<R: Read>(reader: &mut R, chunk_type: &[u8], length: u32) -> std::io::Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    let mut remaining = length as usize;
+    let mut buffer = [0u8; 8192];
+    while remaining > 0 {
+        let block = remaining.min(buffer.len());
+        reader.read_exact(&mut buffer[..block])?;
+        hasher.update(&buffer[..block]);
+        remaining -= block;
     }
+    Ok(hasher.finalize())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn parse_chunk_data<'a>(
+    chunk_type: &[u8; 4],
+    data: &'a [u8],
+    crc: u32,
+) -> Result<Chunk, nom::Err<(&'a [u8], nom::error::ErrorKind)>> {
+    let (_, chunk) = match chunk_type {
+        b"IHDR" => parse_image_header_chunk(data, crc)?,
+        b"tEXt" => parse_text_chunk(data, crc)?,
+        b"iTXt" => parse_international_text_chunk(data, crc)?,
+        b"gAMA" => parse_image_gamma_chunk(data, crc)?,
+        b"pHYs" => parse_physical_dimensions_chunk(data, crc)?,
+        b"eXIf" => (data, Chunk::Exif(data.to_vec(), crc)),
+        b"IEND" => (data, Chunk::End(crc)),
+        _ => (
+            data,
+            Chunk::Other(String::from_utf8_lossy(chunk_type).into_owned(), crc),
+        ),
+    };
+    Ok(chunk)
 }
 
 fn take_str(input: &[u8], length: u32) -> IResult<&[u8], &str> {
     let (input, value) = take(length)(input)?;
-    match std::str::from_utf8(&value) {
+    match std::str::from_utf8(value) {
         Ok(string) => Ok((input, string)),
-        Err(e) => Err(nom::Err::Error(nom::error::ParseError::from_error_kind(
+        Err(_) => Err(nom::Err::Error(nom::error::ParseError::from_error_kind(
             input,
             nom::error::ErrorKind::Char,
         ))),
     }
 }
 
-fn parse_text_chunk(input: &[u8], length: u32) -> IResult<&[u8], Chunk> {
-    let (input, value) = take(length)(input)?;
-    let (input, crc) = be_u32(input)?;
-
-    let (key, value) = match key_value(value) {
+fn parse_text_chunk(input: &[u8], crc: u32) -> IResult<&[u8], Chunk> {
+    let (key, value) = match key_value(input) {
         Ok((k, v)) => (k, v),
-        Err(e) => {
+        Err(_) => {
             return Err(nom::Err::Error(nom::error::ParseError::from_error_kind(
                 input,
-                nom::error::ErrorKind::Eof,
+                nom::error::ErrorKind::Char,
             )))
         }
     };
-    Ok((input, Chunk::Text(key, value, crc)))
+    Ok((&input[input.len()..], Chunk::Text(key, value, crc)))
 }
 
-fn take_str_null_delim<'a>(input: &'a [u8]) -> IResult<&[u8], (&'a str, u32)> {
+fn take_str_null_delim(input: &[u8]) -> IResult<&[u8], &str> {
     let (input, value) = take_till(|b| b == 0)(input)?;
-    let bytes_taken = value.len();
-    match std::str::from_utf8(&value) {
-        Ok(string) => Ok((input, (string, bytes_taken as u32))),
-        Err(e) => Err(nom::Err::Error(nom::error::ParseError::from_error_kind(
+    match std::str::from_utf8(value) {
+        Ok(string) => Ok((input, string)),
+        Err(_) => Err(nom::Err::Error(nom::error::ParseError::from_error_kind(
             input,
             nom::error::ErrorKind::Char,
         ))),
     }
 }
 
-fn parse_international_text_chunk(input: &[u8], length: u32) -> IResult<&[u8], Chunk> {
-    let mut bytes_total = 0;
-    let (input, (keyword, bytes_taken)) = take_str_null_delim(input)?;
-    bytes_total = bytes_total + bytes_taken;
+fn parse_international_text_chunk(input: &[u8], crc: u32) -> IResult<&[u8], Chunk> {
+    let (input, keyword) = take_str_null_delim(input)?;
     let (input, _delim) = take(ONE)(input)?;
-    bytes_total = bytes_total + 1;
     let (input, compression_flag) = be_u8(input)?;
-    bytes_total = bytes_total + 1;
-
     let (input, compression_method) = be_u8(input)?;
-    bytes_total = bytes_total + 1;
 
-    let (input, (language_tag, bytes_taken)) = take_str_null_delim(input)?;
-    bytes_total = bytes_total + bytes_taken;
+    let (input, language_tag) = take_str_null_delim(input)?;
     let (input, _delim) = take(ONE)(input)?;
-    bytes_total = bytes_total + 1;
 
-    let (input, (translated_keyword, bytes_taken)) = take_str_null_delim(input)?;
-    bytes_total = bytes_total + bytes_taken;
+    let (input, translated_keyword) = take_str_null_delim(input)?;
     let (input, _delim) = take(ONE)(input)?;
-    bytes_total = bytes_total + 1;
 
-    let (input, text) = take_str(input, length - bytes_total)?;
-    let (input, crc) = be_u32(input)?;
+    let (input, text) = take_str(input, input.len() as u32)?;
 
     let international_text = InternationalText {
         keyword: keyword.to_owned(),
-        compression_flag: compression_flag == 255,
+        compression_flag: compression_flag != 0,
         compression_method,
         language_tag: language_tag.to_owned(),
         translated_keyword: translated_keyword.to_owned(),
@@ -199,7 +316,7 @@ fn parse_international_text_chunk(input: &[u8], length: u32) -> IResult<&[u8], C
     Ok((input, Chunk::InternationalText(international_text, crc)))
 }
 
-fn parse_image_header_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
+fn parse_image_header_chunk(input: &[u8], crc: u32) -> IResult<&[u8], Chunk> {
     let (input, width) = be_u32(input)?;
     let (input, height) = be_u32(input)?;
     let (input, bit_depth) = be_u8(input)?;
@@ -207,7 +324,6 @@ fn parse_image_header_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
     let (input, compression_method) = be_u8(input)?;
     let (input, filter_method) = be_u8(input)?;
     let (input, interlace_method) = be_u8(input)?;
-    let (input, crc) = be_u32(input)?;
     let image_header = ImageHeader {
         width,
         height,
@@ -220,26 +336,16 @@ fn parse_image_header_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
     Ok((input, Chunk::ImageHeader(image_header, crc)))
 }
 
-fn parse_image_gamma_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
+fn parse_image_gamma_chunk(input: &[u8], crc: u32) -> IResult<&[u8], Chunk> {
     let (input, gamma) = be_u32(input)?;
-    let (input, crc) = be_u32(input)?;
     Ok((input, Chunk::ImageGamma(gamma, crc)))
 }
 
-fn parse_end_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
-    // be_u32(input); // consume checksum if present
-    Ok((input, Chunk::End))
-}
-
-fn parse_other_chunk<'a>(
-    input: &'a [u8],
-    chunk_type: &str,
-    length: u32,
-) -> IResult<&'a [u8], Chunk> {
-    let (input, value) = take(length)(input)?;
-    let (input, crc) = be_u32(input)?;
-    let chunk = Chunk::Other(chunk_type.to_owned(), Vec::from(value), crc);
-    Ok((input, chunk))
+fn parse_physical_dimensions_chunk(input: &[u8], crc: u32) -> IResult<&[u8], Chunk> {
+    let (input, x) = be_u32(input)?;
+    let (input, y) = be_u32(input)?;
+    let (input, unit) = be_u8(input)?;
+    Ok((input, Chunk::PhysicalDimensions(x, y, unit, crc)))
 }
 
 fn key_value(data: &[u8]) -> Result<(String, String), std::str::Utf8Error> {
@@ -256,17 +362,32 @@ fn key_value(data: &[u8]) -> Result<(String, String), std::str::Utf8Error> {
 #[cfg(test)]
 mod tests {
     use std::fs::File;
-    use std::io::Read;
+    use std::io::{Cursor, Read};
 
-    use crate::image::png::parse_png;
-    use crate::image::png::Chunk;
+    use crate::image::png::{
+        crc32, parse_international_text_chunk, read_png, Chunk, ReadOptions, PNG_SIGNATURE,
+    };
+    use crate::image::Defect;
 
-    #[test]
-    fn it_works() {
+    fn sample() -> Vec<u8> {
         let mut data = Vec::new();
         let mut file = File::open("sample/watergate/simple/MOV_0646000.png").unwrap();
         file.read_to_end(&mut data).unwrap();
-        let (_, png) = parse_png(&data).unwrap();
+        data
+    }
+
+    fn checked() -> ReadOptions {
+        ReadOptions {
+            check_integrity: true,
+            verify_image_data: true,
+            text_after_data: true,
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let png = read_png(&mut Cursor::new(sample()), &checked()).unwrap();
+        assert!(png.defects.is_empty());
         println!("Got the following chunks:");
         for (i, chunk) in png.chunks.iter().enumerate() {
             match chunk {
@@ -279,11 +400,94 @@ mod tests {
                 Chunk::InternationalText(text, _crc) => {
                     println!("{}: TextChunk: {} → {}", i, text.keyword, text.text)
                 }
-                Chunk::End => println!("{}: End", i),
-                Chunk::Other(chunk_type, _value, _crc) => {
+                Chunk::PhysicalDimensions(x, y, unit, _crc) => {
+                    println!("{}: PhysicalDimensions: {} x {} ({})", i, x, y, unit)
+                }
+                Chunk::Exif(data, _crc) => println!("{}: Exif: {} bytes", i, data.len()),
+                Chunk::End(_crc) => println!("{}: End", i),
+                Chunk::Other(chunk_type, _crc) => {
                     println!("{}: OtherChunk of type {}", i, chunk_type)
                 }
             }
         }
     }
+
+    #[test]
+    fn stops_at_image_data() {
+        let png = read_png(&mut Cursor::new(sample()), &ReadOptions::default()).unwrap();
+        assert!(png.chunks.iter().all(|chunk| match chunk {
+            Chunk::Other(chunk_type, _crc) => chunk_type != "IDAT",
+            Chunk::End(_crc) => false,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn detects_damage() {
+        let mut data = sample();
+        // first byte of the IHDR width
+        data[16] ^= 0xff;
+        data.extend_from_slice(b"garbage");
+        let png = read_png(&mut Cursor::new(data), &checked()).unwrap();
+        assert_eq!(
+            png.defects,
+            vec![
+                Defect::ChecksumMismatch("IHDR".to_owned()),
+                Defect::TrailingData(7)
+            ]
+        );
+
+        let truncated = sample()[..1000].to_vec();
+        let png = read_png(&mut Cursor::new(truncated), &checked()).unwrap();
+        assert_eq!(png.defects, vec![Defect::Truncated]);
+    }
+
+    /// Signature and IHDR of the sample, then a chunk with any length.
+    fn with_chunk(length: u32, chunk_type: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut png = sample()[..33].to_vec();
+        png.extend_from_slice(&length.to_be_bytes());
+        png.extend_from_slice(chunk_type);
+        png.extend_from_slice(data);
+        png.extend_from_slice(&crc32(chunk_type, data).to_be_bytes());
+        png
+    }
+
+    #[test]
+    fn rejects_truncated_international_text() {
+        let broken: [&[u8]; 6] = [
+            b"",
+            b"Title",
+            b"Title\0\0",
+            b"Title\0\0\0en",
+            b"Title\0\0\0en\0Titel",
+            b"Title\0\0\0en\0Titel\0\xff\xfe",
+        ];
+        for data in &broken {
+            assert!(
+                parse_international_text_chunk(data, 0).is_err(),
+                "{:?}",
+                data
+            );
+            let png = with_chunk(data.len() as u32, b"iTXt", data);
+            let png = read_png(&mut Cursor::new(png), &checked()).unwrap();
+            assert_eq!(png.defects[0], Defect::Malformed("iTXt".to_owned()));
+        }
+        assert!(parse_international_text_chunk(b"Title\0\0\0en\0Titel\0", 0).is_ok());
+    }
+
+    #[test]
+    fn rejects_chunk_lengths_beyond_the_file() {
+        // not allocated, the file is too short for it
+        for length in &[0x7fff_ffff, 0xffff_ffff, 1000] {
+            let png = with_chunk(*length, b"tEXt", b"a\0b");
+            let png = read_png(&mut Cursor::new(png), &checked()).unwrap();
+            assert_eq!(png.defects, vec![Defect::Truncated]);
+            assert_eq!(png.chunks.len(), 1);
+        }
+        // without an image header there is no image
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend_from_slice(&0xffff_ffffu32.to_be_bytes());
+        data.extend_from_slice(b"IHDR\0\0\0\0\0\0\0\0");
+        assert!(read_png(&mut Cursor::new(data), &checked()).is_err());
+    }
 }
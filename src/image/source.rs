@@ -1,128 +1,852 @@
+use crate::error::{Error, Result};
+use crate::image::index::Index;
+use crate::image::label::{Label, LabelValue, Translation};
 use crate::image::Format;
-use crate::image::Label;
+use crate::image::Integrity;
+use crate::image::Kind;
+use crate::image::Orientation;
+use crate::image::Page;
+use crate::image::Resolution;
+use crate::image::Tiling;
+use crate::validator::Validator;
 
+use serde::{Deserialize, Serialize};
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 
-use crate::config::Config;
-use crate::image::png::{Chunk, PNG};
+use crate::av::mp3::MP3;
+use crate::av::mp4::MP4;
+use crate::av::wav::WAV;
+use crate::av::webm::WebM;
+use crate::cache::{Cache, Stats};
+use crate::config::{Config, Images, IntegrityMode};
+use crate::image::bmp::BMP;
+use crate::image::dicom::DICOM;
+use crate::image::exif::Exif;
+use crate::image::gif::GIF;
+use crate::image::isobmff::HEIF;
+use crate::image::jp2::JP2;
+use crate::image::jpeg::JPEG;
+use crate::image::jxl::JXL;
+use crate::image::png::{Chunk, InternationalText, ReadOptions, PNG};
+use crate::image::svg::SVG;
+use crate::image::tiff::TIFF;
+use crate::image::webp::WebP;
+use crate::image::xmp;
 
+// iTXt keyword of XMP packets in PNG files
+const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Image {
     pub format: Format,
+    pub kind: Kind,
     pub name: String,
     pub width: u32,
+    /// Stored size, before applying the orientation
     pub height: u32,
+    pub orientation: Orientation,
     pub labels: Vec<Label>,
+    pub integrity: Integrity,
+    pub tiling: Option<Tiling>,
+    pub page: Option<Page>,
+    pub resolution: Option<Resolution>,
+    /// Length of sound and video, in seconds
+    pub duration: Option<f64>,
 }
 
 pub struct ImageSource {
     config: Config,
+    /// Images read from files, by path with the size and mtime as version
+    images: Cache<PathBuf, Vec<Image>>,
+    /// The same on disk, if configured
+    index: Option<Index>,
+    /// Threads that may read files, shared by all requests
+    scan_slots: Slots,
+}
+
+/// A number of threads shared by concurrent scans.
+struct Slots {
+    count: usize,
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A slot taken to read one file, given back when dropped.
+struct Permit<'a> {
+    slots: &'a Slots,
 }
 
 impl ImageSource {
     pub fn new(config: Config) -> ImageSource {
-        ImageSource { config }
+        let images = Cache::new(&config.cache);
+        let index = config.images.index.as_ref().and_then(|path| {
+            match Index::open(path, &index_fingerprint(&config.images)) {
+                Ok(index) => {
+                    println!("Using index {} ({} files)", path.display(), index.len());
+                    Some(index)
+                }
+                Err(e) => {
+                    println!("Could not open index {}: {}", path.display(), e);
+                    None
+                }
+            }
+        });
+        let threads = match config.serving.scan_threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            threads => threads,
+        };
+        ImageSource {
+            config,
+            images,
+            index,
+            scan_slots: Slots::new(threads),
+        }
     }
 
     /// Returns all images in a directory inside self.path.
-    /// This can also be an empty list if there are no images
-    /// or None if the directory does not exist.
+    /// This can also be an empty list if there are no images.
+    /// Files that cannot be read are reported and left out.
     ///
-    pub fn load(&self, sub_path: &str) -> Option<Vec<Image>> {
-        let restored = sub_path.replace(
-            &self.config.urls.path_sep,
-            &std::path::MAIN_SEPARATOR.to_string(),
-        );
-        let source_path = self.config.serving.path.join(restored);
-
-        if !source_path.exists() {
-            return None;
-        }
+    pub fn load(&self, sub_path: &str) -> Result<Vec<Image>> {
+        let source_path = self.directory(sub_path)?;
 
-        let mut dir_entries: Vec<_> = std::fs::read_dir(&source_path)
-            .unwrap()
-            .map(|p| p.unwrap())
+        let mut dir_entries: Vec<_> = std::fs::read_dir(&source_path)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| self.is_served(entry))
             .collect();
         dir_entries.sort_by_key(|dir_entry| dir_entry.path());
 
-        let mut images = Vec::with_capacity(dir_entries.len());
-        for entry in dir_entries.iter() {
-            let path = entry.path();
-            match Image::for_file(&path) {
-                Some(image) => images.push(image),
-                None => (),
+        let paths: Vec<PathBuf> = dir_entries.iter().map(|entry| entry.path()).collect();
+        let mut images = Vec::with_capacity(paths.len());
+        for (path, result) in paths.iter().zip(self.read_files(&paths)) {
+            let file_images = match result {
+                Ok(file_images) => file_images,
+                Err(e) => {
+                    println!("Could not read {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            for image in file_images {
+                if let Integrity::Damaged(defects) = &image.integrity {
+                    for defect in defects {
+                        println!("Damaged image {}: {}", path.display(), defect);
+                    }
+                    if self.config.images.integrity == IntegrityMode::Strict {
+                        println!("Skipping {} (strict integrity mode)", path.display());
+                        continue;
+                    }
+                }
+                images.push(image);
+            }
+        }
+        Ok(images)
+    }
+
+    /// Reads files in parallel, on at most the share of `serving.scan
+    /// threads` one request may use. The results are in the order of
+    /// `paths`.
+    fn read_files(&self, paths: &[PathBuf]) -> Vec<std::io::Result<Vec<Image>>> {
+        let threads = self.scan_slots.share().min(paths.len());
+        if threads <= 1 {
+            return paths.iter().map(|path| self.read_file(path)).collect();
+        }
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<std::io::Result<Vec<Image>>> = Vec::with_capacity(paths.len());
+        results.resize_with(paths.len(), || {
+            Err(std::io::Error::other("reading the file failed"))
+        });
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut read = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            match paths.get(index) {
+                                Some(path) => read.push((index, self.read_file(path))),
+                                None => return read,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                // panics are caught for each file, so workers finish
+                for (index, result) in worker.join().unwrap_or_default() {
+                    results[index] = result;
+                }
+            }
+        });
+        results
+    }
+
+    /// Images of a file. A parser that panics fails only this file.
+    fn read_file(&self, path: &PathBuf) -> std::io::Result<Vec<Image>> {
+        without_panics(|| self.images_for_file(path))
+    }
+
+    /// Images of a file, from the cache or the index if the file did not
+    /// change. Only parsing the file waits for one of the scan threads,
+    /// which all requests share.
+    fn images_for_file(&self, path: &PathBuf) -> std::io::Result<Vec<Image>> {
+        let metadata = path.metadata()?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH);
+        let version = format!(
+            "{}:{}",
+            metadata.len(),
+            modified.unwrap_or_default().as_nanos()
+        );
+        if let Some(images) = self.images.get(path, &version) {
+            return Ok(images);
+        }
+        let indexed = self
+            .index
+            .as_ref()
+            .and_then(|index| index.get(path, &version));
+        let images = match indexed {
+            Some(images) => images,
+            None => {
+                let permit = self.scan_slots.acquire();
+                let images = Image::for_file(path, &self.config.images)?;
+                drop(permit);
+                // directories change with every file added
+                let index = self.index.as_ref().filter(|_| metadata.is_file());
+                if let Some(index) = index {
+                    if let Err(e) = index.insert(path, &version, &images) {
+                        println!("Could not index {}: {}", path.display(), e);
+                    }
+                }
+                images
             }
+        };
+        let size = images.iter().map(Image::approximate_size).sum();
+        self.images
+            .insert(path.clone(), version, images.clone(), size);
+        Ok(images)
+    }
+
+    pub fn cache_stats(&self) -> Stats {
+        self.images.stats()
+    }
+
+    /// Path and format of a file to serve directly. Only plain file names
+    /// of supported formats are served.
+    pub fn file(&self, sub_path: &str, name: &str) -> Result<(PathBuf, Format)> {
+        let not_found = || Error::NotFound(format!("{}/files/{}", sub_path, name));
+        if Path::new(name).file_name() != Some(OsStr::new(name)) || name.starts_with('.') {
+            return Err(not_found());
+        }
+        let path = self.directory(sub_path)?.join(name);
+        if !path.is_file() {
+            return Err(not_found());
+        }
+        if !self.config.is_served(&path) {
+            println!("Not serving {}: outside of serving.path", path.display());
+            return Err(Error::BadIdentifier(format!("{}/files/{}", sub_path, name)));
         }
-        Some(images)
+        match Format::detect(&path) {
+            Ok(Some(format)) => Ok((path, format)),
+            Ok(None) => Err(not_found()),
+            Err(e) => Err(Error::UnreadableFile(path, e)),
+        }
+    }
+
+    /// Validator of the manifest for a directory.
+    pub fn manifest_validator(&self, sub_path: &str) -> Result<Validator> {
+        let path = self.directory(sub_path)?;
+        Ok(Validator::for_manifest(
+            &path,
+            &format!("{:?}", self.config),
+        )?)
+    }
+
+    /// Validator of the collection for a directory.
+    pub fn collection_validator(&self, sub_path: &str) -> Result<Validator> {
+        let path = self.directory(sub_path)?;
+        Ok(Validator::for_collection(
+            &path,
+            &format!("{:?}", self.config),
+        )?)
+    }
+
+    /// Whether the configured index could be opened.
+    pub fn has_index(&self) -> bool {
+        self.index.is_some()
+    }
+
+    /// How long a manifest or collection may take.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.serving.timeout)
+    }
+
+    fn directory(&self, sub_path: &str) -> Result<PathBuf> {
+        self.config.directory(sub_path)
+    }
+
+    /// Entries of a served directory are inside `serving.path` unless they
+    /// are symbolic links.
+    fn is_served(&self, entry: &std::fs::DirEntry) -> bool {
+        match entry.file_type() {
+            Ok(file_type) if !file_type.is_symlink() => true,
+            _ => self.config.is_served(&entry.path()),
+        }
+    }
+}
+
+impl Slots {
+    fn new(count: usize) -> Slots {
+        let count = count.max(1);
+        Slots {
+            count,
+            free: Mutex::new(count),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Threads one scan may read files on: half of them, so a large
+    /// directory leaves room for the others.
+    fn share(&self) -> usize {
+        self.count.div_ceil(2)
+    }
+
+    /// Takes a slot, waiting until one is free.
+    fn acquire(&self) -> Permit<'_> {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap_or_else(|e| e.into_inner());
+        }
+        *free -= 1;
+        Permit { slots: self }
     }
 }
 
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.slots.free.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        self.slots.freed.notify_one();
+    }
+}
+
+/// Turns a panic into an error. The panic is reported by the panic hook.
+fn without_panics<T>(work: impl FnOnce() -> std::io::Result<T>) -> std::io::Result<T> {
+    catch_unwind(AssertUnwindSafe(work))
+        .unwrap_or_else(|_| Err(std::io::Error::other("reading the file panicked")))
+}
+
+/// Raised when files are read differently within a version, e.g. when
+/// DICOM study tags were no longer exposed.
+const INDEX_REVISION: u32 = 2;
+
+/// Changes whenever the same file would give other images, so an index
+/// written by another version or with other options is not used.
+fn index_fingerprint(options: &Images) -> String {
+    format!(
+        "{}.{} {:?} {} {}",
+        env!("CARGO_PKG_VERSION"),
+        INDEX_REVISION,
+        options.integrity,
+        options.verify_image_data,
+        options.text_after_data
+    )
+}
+
 impl Image {
-    pub fn for_file(path: &PathBuf) -> Option<Image> {
-        if path.extension().is_none() {
-            return None; // Skip if it's not an image and has no extension
+    pub fn new(name: String, format: Format, width: u32, height: u32) -> Image {
+        Image {
+            format,
+            kind: Kind::Image,
+            name,
+            width,
+            height,
+            orientation: Orientation::default(),
+            labels: Vec::new(),
+            integrity: Integrity::Unchecked,
+            tiling: None,
+            page: None,
+            resolution: None,
+            duration: None,
         }
+    }
+
+    /// A sound if there is no size, a video otherwise.
+    pub fn timed(name: String, format: Format, width: u32, height: u32, duration: f64) -> Image {
+        let mut image = Image::new(name, format, width, height);
+        image.kind = if width == 0 || height == 0 {
+            Kind::Sound
+        } else {
+            Kind::Video
+        };
+        image.duration = Some(duration);
+        image
+    }
+
+    /// Memory used by the image in bytes, roughly, to limit caches.
+    pub fn approximate_size(&self) -> usize {
+        let labels: usize = self
+            .labels
+            .iter()
+            .map(|label| std::mem::size_of::<Label>() + label.to_string().len())
+            .sum();
+        std::mem::size_of::<Image>() + self.name.len() + labels
+    }
 
-        let name: String = match path.file_name() {
-            Some(n) => n.to_str().unwrap().to_owned(),
+    /// Media type of the file, telling audio-only containers from videos.
+    pub fn media_type(&self) -> &str {
+        match (&self.format, self.kind) {
+            (Format::MP4, Kind::Sound) => "audio/mp4",
+            (Format::WEBM, Kind::Sound) => "audio/webm",
+            (format, _) => format.media_type(),
+        }
+    }
+
+    /// Reads format, dimensions and labels of an image file, one image for
+    /// each page of multi-page files. Returns an empty list for files that
+    /// are not supported images and an error if a supported file cannot be
+    /// read or parsed.
+    pub fn for_file(path: &PathBuf, options: &Images) -> std::io::Result<Vec<Image>> {
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let format = match Format::detect(path)? {
+            Some(format) => format,
+            None => return Ok(Vec::new()),
+        };
+
+        let name: String = match path.file_name().map(OsStr::to_str) {
+            Some(Some(n)) => n.to_owned(),
+            Some(None) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "file name is not valid UTF-8",
+                ))
+            }
             None => String::new(),
         };
 
-        match path.extension().and_then(OsStr::to_str) {
-            Some("png") => {
-                let png = match PNG::load(path) {
-                    Ok(value) => value,
-                    Err(_) => return None,
+        match format {
+            Format::PNG => {
+                let read_options = ReadOptions {
+                    check_integrity: options.integrity != IntegrityMode::Off,
+                    verify_image_data: options.verify_image_data,
+                    text_after_data: options.text_after_data,
                 };
+                let png = PNG::load(path, &read_options)?;
 
-                let labels: Vec<Label> = png
-                    .chunks
-                    .into_iter()
-                    .filter_map(|chunk| match chunk {
-                        Chunk::Text(key, value, _crc) => Some(Label::KV(key, value)),
+                let mut image = Image::new(name, format, png.width, png.height);
+                for chunk in png.chunks {
+                    match chunk {
+                        Chunk::Text(key, value, _crc) => {
+                            image.labels.push(Label::parse(key, &value))
+                        }
+                        Chunk::InternationalText(text, _crc) if text.keyword == XMP_KEYWORD => {
+                            image.labels.extend(xmp::labels(&text.text))
+                        }
                         Chunk::InternationalText(text, _crc) => {
-                            Some(Label::KV(text.keyword, text.text))
+                            add_international_text(&mut image.labels, text)
+                        }
+                        // unit 1 is the metre, 0 only gives the aspect ratio
+                        Chunk::PhysicalDimensions(x, y, 1, _crc) => {
+                            image.resolution = Some(Resolution {
+                                vertical: f64::from(y),
+                                horizontal: f64::from(x),
+                            })
+                        }
+                        Chunk::Exif(data, _crc) => {
+                            if let Some(exif) = parse_exif(path, Some(data)) {
+                                image.orientation = exif.orientation();
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                image.labels = image.labels.into_iter().map(unwrap_single).collect();
+                if options.integrity != IntegrityMode::Off {
+                    image.integrity = Integrity::from_defects(png.defects);
+                }
+                Ok(vec![image])
+            }
+            Format::JP2 | Format::JPX | Format::J2K => {
+                let jp2 = JP2::load(path)?;
+                let mut image = Image::new(name, format, jp2.width, jp2.height);
+                image.resolution = jp2.capture_resolution.or(jp2.display_resolution);
+                let codestream = jp2.codestream;
+                if codestream.tile_width > 0 && codestream.tile_height > 0 {
+                    image.tiling = Some(Tiling {
+                        width: codestream.tile_width.min(jp2.width),
+                        height: codestream.tile_height.min(jp2.height),
+                        levels: codestream.resolution_levels.max(1),
+                    });
+                }
+                Ok(vec![image])
+            }
+            Format::JPEG => {
+                let jpeg = JPEG::load(path)?;
+                let mut image = Image::new(name, format, jpeg.width, jpeg.height);
+                let exif = parse_exif(path, jpeg.exif);
+                if let Some(exif) = &exif {
+                    image.orientation = exif.orientation();
+                }
+                image.labels = embedded_labels(exif, jpeg.xmp);
+                Ok(vec![image])
+            }
+            Format::TIFF => {
+                let tiff = TIFF::load(path)?;
+                let count = tiff.pages.len() as u32;
+                let images = tiff
+                    .pages
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, page)| {
+                        let mut image =
+                            Image::new(name.clone(), Format::TIFF, page.width, page.height);
+                        let exif = Exif {
+                            primary: page.ifd,
+                            exif: None,
+                        };
+                        image.orientation = exif.orientation();
+                        image.resolution = exif.primary.resolution();
+                        image.labels = exif.labels();
+                        if count > 1 {
+                            image.page = Some(Page {
+                                index: index as u32,
+                                count,
+                            });
                         }
-                        _ => None,
+                        image
                     })
                     .collect();
-
-                Some(Image {
-                    name,
-                    format: Format::PNG,
-                    width: png.width,
-                    height: png.height,
-                    labels,
-                })
+                Ok(images)
             }
-            Some("jpg") | Some("jpeg") => {
-                let dimensions = match imagesize::size(path) {
-                    Ok(dim) => dim,
-                    Err(_) => return None,
-                };
-
-                Some(Image {
-                    name,
-                    format: Format::JPEG,
-                    width: dimensions.width as u32,
-                    height: dimensions.height as u32,
-                    labels: Vec::new(),
-                })
+            Format::WEBP => {
+                let webp = WebP::load(path)?;
+                let mut image = Image::new(name, format, webp.width, webp.height);
+                image.labels = embedded_labels(parse_exif(path, webp.exif), webp.xmp);
+                Ok(vec![image])
             }
-            Some("tif") | Some("tiff") => {
-                let dimensions = match imagesize::size(path) {
-                    Ok(dim) => dim,
-                    Err(_) => return None,
-                };
-                Some(Image {
+            Format::AVIF | Format::HEIC | Format::HEIF => {
+                let heif = HEIF::load(path)?;
+                let mut image = Image::new(name, format, heif.width, heif.height);
+                // the EXIF orientation is informative only, irot and imir apply
+                image.orientation = heif.orientation;
+                image.labels = embedded_labels(parse_exif(path, heif.exif), heif.xmp);
+                Ok(vec![image])
+            }
+            Format::JXL => {
+                let jxl = JXL::load(path)?;
+                let mut image = Image::new(name, format, jxl.width, jxl.height);
+                image.orientation = jxl.orientation;
+                image.labels = embedded_labels(parse_exif(path, jxl.exif), jxl.xmp);
+                Ok(vec![image])
+            }
+            Format::GIF => {
+                let gif = GIF::load(path)?;
+                let mut image = Image::new(name, format, gif.width, gif.height);
+                image.labels = embedded_labels(None, gif.xmp);
+                Ok(vec![image])
+            }
+            Format::BMP => {
+                let bmp = BMP::load(path)?;
+                Ok(vec![Image::new(name, format, bmp.width, bmp.height)])
+            }
+            Format::SVG => {
+                let svg = SVG::load(path)?;
+                Ok(vec![Image::new(name, format, svg.width, svg.height)])
+            }
+            Format::DICOM => {
+                let dicom = DICOM::load(path)?;
+                let labels = dicom.labels();
+                let count = dicom.frames;
+                let images = (0..count)
+                    .map(|index| {
+                        let mut image =
+                            Image::new(name.clone(), Format::DICOM, dicom.columns, dicom.rows);
+                        image.labels = labels.clone();
+                        if count > 1 {
+                            image.page = Some(Page { index, count });
+                        }
+                        image
+                    })
+                    .collect();
+                Ok(images)
+            }
+            Format::MP3 => {
+                let mp3 = MP3::load(path)?;
+                Ok(vec![Image::timed(name, format, 0, 0, mp3.duration)])
+            }
+            Format::WAV => {
+                let wav = WAV::load(path)?;
+                Ok(vec![Image::timed(name, format, 0, 0, wav.duration)])
+            }
+            Format::MP4 => {
+                let mp4 = MP4::load(path)?;
+                let mut image = Image::timed(name, format, mp4.width, mp4.height, mp4.duration);
+                image.orientation = mp4.orientation;
+                Ok(vec![image])
+            }
+            Format::WEBM => {
+                let webm = WebM::load(path)?;
+                Ok(vec![Image::timed(
                     name,
-                    format: Format::TIFF,
-                    width: dimensions.width as u32,
-                    height: dimensions.height as u32,
-                    labels: Vec::new(),
-                })
+                    format,
+                    webm.width,
+                    webm.height,
+                    webm.duration,
+                )])
+            }
+        }
+    }
+}
+
+/// Adds an iTXt entry to the label with the same keyword, so translations
+/// of a text end up in one localized label.
+fn add_international_text(labels: &mut Vec<Label>, text: InternationalText) {
+    let translation = Translation {
+        language: text.language_tag,
+        key: if text.translated_keyword.is_empty() {
+            text.keyword.clone()
+        } else {
+            text.translated_keyword
+        },
+        value: text.text,
+    };
+    for label in labels.iter_mut() {
+        if let LabelValue::Localized(translations) = &mut label.value {
+            if label.key == text.keyword {
+                translations.push(translation);
+                return;
             }
-            _ => None,
         }
     }
+    labels.push(Label::new(
+        text.keyword,
+        LabelValue::Localized(vec![translation]),
+    ));
+}
+
+/// A localized label with a single text of unknown language is a plain label.
+fn unwrap_single(label: Label) -> Label {
+    match &label.value {
+        LabelValue::Localized(translations)
+            if translations.len() == 1 && translations[0].language.is_empty() =>
+        {
+            Label::parse(label.key.clone(), &translations[0].value)
+        }
+        _ => label,
+    }
+}
+
+/// Parses an embedded EXIF block. Broken EXIF data is reported, but does
+/// not make the image unusable.
+fn parse_exif(path: &Path, data: Option<Vec<u8>>) -> Option<Exif> {
+    match Exif::parse(&data?) {
+        Ok(exif) => Some(exif),
+        Err(e) => {
+            println!("Could not read EXIF of {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Labels from EXIF and XMP blocks embedded in a container.
+fn embedded_labels(exif: Option<Exif>, xmp: Option<String>) -> Vec<Label> {
+    let mut labels = Vec::new();
+    if let Some(exif) = exif {
+        labels.extend(exif.labels());
+    }
+    if let Some(packet) = xmp {
+        labels.extend(xmp::labels(&packet));
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, Images};
+    use crate::image::label::{Label, LabelValue, Translation};
+    use crate::image::source::{without_panics, Image, ImageSource, Slots};
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn chunk(chunk_type: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(chunk_type);
+        chunk.extend_from_slice(data);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(chunk_type);
+        hasher.update(data);
+        chunk.extend_from_slice(&hasher.finalize().to_be_bytes());
+        chunk
+    }
+
+    fn international_text(keyword: &str, language: &str, translated: &str, text: &str) -> Vec<u8> {
+        let data = [keyword, "\0\0\0", language, "\0", translated, "\0", text].concat();
+        chunk(b"iTXt", data.as_bytes())
+    }
+
+    fn translation(language: &str, key: &str, value: &str) -> Translation {
+        Translation {
+            language: language.to_owned(),
+            key: key.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn groups_international_text_by_keyword() {
+        let sample = std::fs::read("sample/watergate/simple/MOV_0646000.png").unwrap();
+        // signature and IHDR
+        let mut data = sample[..33].to_vec();
+        data.extend(international_text("Title", "en", "", "Harbour"));
+        data.extend(international_text("Author", "", "", "Jane Roe"));
+        data.extend(international_text("Title", "de", "Titel", "Hafen"));
+        data.extend(chunk(b"IEND", b""));
+        let path = std::env::temp_dir().join(format!("forager-itxt-{}.png", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let images = Image::for_file(&path, &Images::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            images[0].labels,
+            vec![
+                Label::new(
+                    "Title",
+                    LabelValue::Localized(vec![
+                        translation("en", "Title", "Harbour"),
+                        translation("de", "Titel", "Hafen"),
+                    ])
+                ),
+                // a single text of unknown language is a plain label
+                Label::parse("Author", "Jane Roe"),
+            ]
+        );
+    }
+
+    fn scan_config(root: &Path, threads: usize) -> Config {
+        let mut config: Config = serde_yaml::from_str(&format!(
+            "
+            serving: {{path: samples, host: localhost, port: 7890, scan threads: {}}}
+            urls: {{path sep: '-', image api: '', presentation api: ''}}
+            ",
+            threads
+        ))
+        .unwrap();
+        config.serving.path = root.to_owned();
+        config
+    }
+
+    #[test]
+    fn takes_a_scan_thread_only_to_parse_files() {
+        let root = std::env::temp_dir().join(format!("forager-slots-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let sample = std::fs::read("sample/watergate/simple/MOV_0646000.png").unwrap();
+        let (cached, parsed) = (root.join("cached.png"), root.join("parsed.png"));
+        std::fs::write(&cached, &sample).unwrap();
+        std::fs::write(&parsed, &sample).unwrap();
+        let source = ImageSource::new(scan_config(&root, 2));
+        assert!(source.read_file(&cached).is_ok());
+
+        // another scan holds all threads
+        let held = vec![source.scan_slots.acquire(), source.scan_slots.acquire()];
+        let (sender, receiver) = mpsc::channel();
+        std::thread::scope(|scope| {
+            scope.spawn(|| sender.send(("cached", source.read_file(&cached).is_ok())));
+            assert_eq!(
+                receiver.recv_timeout(Duration::from_secs(5)),
+                Ok(("cached", true))
+            );
+            scope.spawn(|| sender.send(("parsed", source.read_file(&parsed).is_ok())));
+            assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+            drop(held);
+            assert_eq!(
+                receiver.recv_timeout(Duration::from_secs(5)),
+                Ok(("parsed", true))
+            );
+        });
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn leaves_scan_threads_to_other_requests() {
+        assert_eq!(Slots::new(0).share(), 1);
+        assert_eq!(Slots::new(1).share(), 1);
+        assert_eq!(Slots::new(4).share(), 2);
+        assert_eq!(Slots::new(5).share(), 3);
+    }
+
+    #[test]
+    fn turns_parser_panics_into_errors() {
+        let result: std::io::Result<()> = without_panics(|| panic!("broken parser"));
+        assert!(result.is_err());
+        assert_eq!(without_panics(|| Ok(7)).unwrap(), 7);
+    }
+
+    #[test]
+    fn reads_resolution_from_png_physical_dimensions() {
+        let sample = std::fs::read("sample/watergate/simple/MOV_0646000.png").unwrap();
+        let path = std::env::temp_dir().join(format!("forager-phys-{}.png", std::process::id()));
+        let resolution = |unit: u8| {
+            // signature and IHDR
+            let mut data = sample[..33].to_vec();
+            let mut physical = 3937u32.to_be_bytes().to_vec();
+            physical.extend_from_slice(&7874u32.to_be_bytes());
+            physical.push(unit);
+            data.extend(chunk(b"pHYs", &physical));
+            data.extend(chunk(b"IEND", b""));
+            std::fs::write(&path, data).unwrap();
+            Image::for_file(&path, &Images::default()).unwrap()[0].resolution
+        };
+        // unit 1 is the metre
+        let metres = resolution(1).unwrap();
+        assert_eq!((metres.horizontal, metres.vertical), (3937.0, 7874.0));
+        // unit 0 only gives the aspect ratio
+        assert!(resolution(0).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_file_names_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let sample = std::fs::read("sample/watergate/simple/MOV_0646000.png").unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"scan-\xff.png");
+        let path = std::env::temp_dir()
+            .join(format!("forager-name-{}", std::process::id()))
+            .join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, sample).unwrap();
+        let result = Image::for_file(&path, &Images::default());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn keeps_the_order_of_files_read_in_parallel() {
+        let root = std::env::temp_dir().join(format!("forager-scan-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let sample = std::fs::read("sample/watergate/simple/MOV_0646000.png").unwrap();
+        let mut paths = Vec::new();
+        for i in 0..24 {
+            let path = root.join(format!("{:02}.png", i));
+            // large and small files, so they are not done in order
+            let length = if i % 3 == 0 { sample.len() } else { 33 };
+            std::fs::write(&path, &sample[..length]).unwrap();
+            paths.push(path);
+        }
+        paths.push(root.join("missing.png"));
+        let results = ImageSource::new(scan_config(&root, 8)).read_files(&paths);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(results.len(), paths.len());
+        for (i, result) in results[..24].iter().enumerate() {
+            assert_eq!(result.as_ref().unwrap()[0].name, format!("{:02}.png", i));
+        }
+        assert!(results[24].is_err());
+    }
 }
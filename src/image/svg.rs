@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use crate::image::invalid_data;
+
+// The root element follows the prolog, which is short in practice
+const MAX_PROLOG_LENGTH: u64 = 64 * 1024;
+// CSS pixels per inch
+const DPI: f64 = 96.0;
+
+/// Size of an SVG image from the `width`, `height` and `viewBox` attributes
+/// of the root element, in CSS pixels.
+#[derive(Debug, PartialEq)]
+pub struct SVG {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SVG {
+    pub fn load(path: &PathBuf) -> std::io::Result<SVG> {
+        let mut file = File::open(path)?;
+        read_svg(&mut file)
+    }
+}
+
+pub fn read_svg<R: Read>(reader: &mut R) -> std::io::Result<SVG> {
+    let mut data = Vec::new();
+    reader.take(MAX_PROLOG_LENGTH).read_to_end(&mut data)?;
+    let text = String::from_utf8_lossy(&data);
+    let tag = root_tag(&text).ok_or_else(|| invalid_data("svg", "missing svg element"))?;
+
+    let width = attribute(tag, "width").and_then(length);
+    let height = attribute(tag, "height").and_then(length);
+    let view_box = attribute(tag, "viewBox").and_then(view_box);
+    let (width, height) = match (width, height, view_box) {
+        (Some(width), Some(height), _) => (width, height),
+        (Some(width), None, Some((box_width, box_height))) => {
+            (width, width * box_height / box_width)
+        }
+        (None, Some(height), Some((box_width, box_height))) => {
+            (height * box_width / box_height, height)
+        }
+        (None, None, Some(size)) => size,
+        _ => return Err(invalid_data("svg", "missing size")),
+    };
+    Ok(SVG {
+        width: (width.round() as u32).max(1),
+        height: (height.round() as u32).max(1),
+    })
+}
+
+/// The start tag of the first element, skipping the XML declaration,
+/// comments, processing instructions and the document type.
+fn root_tag(text: &str) -> Option<&str> {
+    let mut rest = text;
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start..];
+        let skip_to = if rest.starts_with("<!--") {
+            rest.find("-->")? + 3
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest.find('>')? + 1
+        } else {
+            break;
+        };
+        rest = &rest[skip_to..];
+    }
+    let end = rest.find('>')?;
+    let tag = &rest[1..end];
+    let name = tag.split_whitespace().next()?;
+    // the element may have a namespace prefix
+    if name == "svg" || name.ends_with(":svg") {
+        Some(tag)
+    } else {
+        None
+    }
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(position) = rest.find(name) {
+        let before = rest[..position].chars().last();
+        let after = rest[position + name.len()..].trim_start();
+        rest = &rest[position + name.len()..];
+        if !matches!(before, Some(c) if c.is_whitespace()) || !after.starts_with('=') {
+            continue;
+        }
+        let value = after[1..].trim_start();
+        let quote = value.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let value = &value[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+    None
+}
+
+/// Converts an absolute length to CSS pixels. Percentages and font relative
+/// units depend on the context and give None.
+fn length(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e'))
+        .unwrap_or(value.len());
+    let number: f64 = value[..split].parse().ok()?;
+    let factor = match value[split..].trim() {
+        "" | "px" => 1.0,
+        "in" => DPI,
+        "cm" => DPI / 2.54,
+        "mm" => DPI / 25.4,
+        "pt" => DPI / 72.0,
+        "pc" => DPI / 6.0,
+        _ => return None,
+    };
+    Some(number * factor).filter(|length| *length > 0.0)
+}
+
+/// Width and height of a `min-x min-y width height` view box.
+fn view_box(value: &str) -> Option<(f64, f64)> {
+    let numbers: Vec<f64> = value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|part| !part.is_empty())
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match numbers.as_slice() {
+        [_, _, width, height] if *width > 0.0 && *height > 0.0 => Some((*width, *height)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::svg::{read_svg, SVG};
+
+    fn size(document: &str) -> Option<SVG> {
+        read_svg(&mut document.as_bytes()).ok()
+    }
+
+    #[test]
+    fn size_from_attributes_and_view_box() {
+        let prolog = "<?xml version=\"1.0\"?>\n<!-- drawing -->\n<!DOCTYPE svg>\n";
+        assert_eq!(
+            size(&format!("{}<svg width=\"200\" height='100px'>", prolog)),
+            Some(SVG {
+                width: 200,
+                height: 100
+            })
+        );
+        assert_eq!(
+            size("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 40 30\" width=\"1in\">"),
+            Some(SVG {
+                width: 96,
+                height: 72
+            })
+        );
+        assert_eq!(
+            size("<svg width=\"100%\" viewBox=\"0,0,640,480\">"),
+            Some(SVG {
+                width: 640,
+                height: 480
+            })
+        );
+        assert_eq!(size("<html><svg width=\"10\" height=\"10\">"), None);
+        assert_eq!(size("<svg width=\"100%\">"), None);
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, Resolution};
+
+// Values larger than this are not loaded (strip offsets of huge images, ICC profiles...)
+const MAX_VALUE_LENGTH: u64 = 1024 * 1024;
+const MAX_ENTRIES: u64 = 4096;
+const MAX_IFDS: usize = 65536;
+
+pub const TAG_IMAGE_WIDTH: u16 = 0x0100;
+pub const TAG_IMAGE_LENGTH: u16 = 0x0101;
+pub const TAG_ORIENTATION: u16 = 0x0112;
+pub const TAG_X_RESOLUTION: u16 = 0x011a;
+pub const TAG_Y_RESOLUTION: u16 = 0x011b;
+pub const TAG_RESOLUTION_UNIT: u16 = 0x0128;
+pub const TAG_SUBFILE_TYPE: u16 = 0x00fe;
+pub const TAG_EXIF_IFD: u16 = 0x8769;
+
+// NewSubfileType flag of reduced resolution copies (pyramids, thumbnails)
+const REDUCED_RESOLUTION: u64 = 1;
+
+/// The pages of a TIFF file.
+#[derive(Debug, PartialEq)]
+pub struct TIFF {
+    pub pages: Vec<TiffPage>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TiffPage {
+    pub width: u32,
+    pub height: u32,
+    pub ifd: Ifd,
+}
+
+impl TIFF {
+    pub fn load(path: &PathBuf) -> std::io::Result<TIFF> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_tiff(&mut file)
+    }
+}
+
+/// Reads all top-level IFDs, leaving out reduced resolution copies of
+/// other pages as found in pyramidal TIFFs.
+pub fn read_tiff<R: Read + Seek>(reader: &mut R) -> std::io::Result<TIFF> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    let mut tiff = TiffReader::new(reader, 0, file_length)?;
+    let mut pages = Vec::new();
+    for ifd in tiff.read_ifds()? {
+        if ifd.unsigned(TAG_SUBFILE_TYPE).unwrap_or(0) & REDUCED_RESOLUTION != 0 {
+            continue;
+        }
+        let width = ifd.unsigned(TAG_IMAGE_WIDTH);
+        let height = ifd.unsigned(TAG_IMAGE_LENGTH);
+        if let (Some(width), Some(height)) = (width, height) {
+            pages.push(TiffPage {
+                width: width as u32,
+                height: height as u32,
+                ifd,
+            });
+        }
+    }
+    if pages.is_empty() {
+        return Err(invalid_data("tiff", "no pages"));
+    }
+    Ok(TIFF { pages })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+/// An image file directory: the tags of one page (or of an EXIF block).
+#[derive(Debug, Default, PartialEq)]
+pub struct Ifd {
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub tag: u16,
+    pub value: Value,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u64>),
+    Rational(Vec<(u32, u32)>),
+    SignedRational(Vec<(i32, i32)>),
+    Undefined(Vec<u8>),
+    /// Value of a type we don't decode or that is too large to load
+    Skipped(u16),
+}
+
+impl Ifd {
+    pub fn get(&self, tag: u16) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .map(|entry| &entry.value)
+    }
+
+    pub fn ascii(&self, tag: u16) -> Option<&str> {
+        match self.get(tag) {
+            Some(Value::Ascii(value)) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+
+    /// First value of an integer tag (BYTE, SHORT or LONG).
+    pub fn unsigned(&self, tag: u16) -> Option<u64> {
+        match self.get(tag)? {
+            Value::Byte(values) => values.first().map(|v| u64::from(*v)),
+            Value::Short(values) => values.first().map(|v| u64::from(*v)),
+            Value::Long(values) => values.first().copied(),
+            _ => None,
+        }
+    }
+
+    pub fn rational(&self, tag: u16) -> Option<(u32, u32)> {
+        match self.get(tag)? {
+            Value::Rational(values) => values.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// Resolution from XResolution, YResolution and ResolutionUnit. None if
+    /// the unit is not absolute or the resolution is missing.
+    pub fn resolution(&self) -> Option<Resolution> {
+        let metres_per_unit = match self.unsigned(TAG_RESOLUTION_UNIT).unwrap_or(2) {
+            2 => 0.0254,
+            3 => 0.01,
+            _ => return None,
+        };
+        let per_metre = |tag| match self.rational(tag)? {
+            (_, 0) => None,
+            (numerator, denominator) => {
+                Some(f64::from(numerator) / f64::from(denominator) / metres_per_unit)
+            }
+        };
+        let horizontal = per_metre(TAG_X_RESOLUTION)?;
+        Some(Resolution {
+            vertical: per_metre(TAG_Y_RESOLUTION).unwrap_or(horizontal),
+            horizontal,
+        })
+    }
+}
+
+/// Reads IFDs of a TIFF structure (classic TIFF or BigTIFF) that starts at
+/// `base` and spans `length` bytes of `reader`. All offsets are relative
+/// to `base`, as in EXIF blocks embedded in other formats.
+pub struct TiffReader<'r, R> {
+    reader: &'r mut R,
+    base: u64,
+    length: u64,
+    order: ByteOrder,
+    big: bool,
+    first_ifd: u64,
+}
+
+impl<'r, R: Read + Seek> TiffReader<'r, R> {
+    pub fn new(reader: &'r mut R, base: u64, length: u64) -> std::io::Result<TiffReader<'r, R>> {
+        reader.seek(SeekFrom::Start(base))?;
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let order = match &header[..2] {
+            b"II" => ByteOrder::Little,
+            b"MM" => ByteOrder::Big,
+            _ => return Err(invalid_data("tiff", "unknown byte order")),
+        };
+        let mut tiff = TiffReader {
+            reader,
+            base,
+            length,
+            order,
+            big: false,
+            first_ifd: 0,
+        };
+        match tiff.u16(&header[2..4]) {
+            42 => tiff.first_ifd = u64::from(tiff.u32(&header[4..8])),
+            43 => {
+                tiff.big = true;
+                let mut offset = [0u8; 8];
+                tiff.reader.read_exact(&mut offset)?;
+                tiff.first_ifd = tiff.u64(&offset);
+            }
+            _ => return Err(invalid_data("tiff", "not a tiff structure")),
+        }
+        Ok(tiff)
+    }
+
+    pub fn byte_order(&self) -> ByteOrder {
+        self.order
+    }
+
+    /// Follows the chain of top-level IFDs (one per page).
+    pub fn read_ifds(&mut self) -> std::io::Result<Vec<Ifd>> {
+        let mut ifds = Vec::new();
+        let mut visited = HashSet::new();
+        let mut offset = self.first_ifd;
+        while offset != 0 && ifds.len() < MAX_IFDS && visited.insert(offset) {
+            let (ifd, next) = self.read_ifd(offset)?;
+            ifds.push(ifd);
+            offset = next;
+        }
+        Ok(ifds)
+    }
+
+    /// Reads a single IFD and returns it with the offset of the next one.
+    pub fn read_ifd(&mut self, offset: u64) -> std::io::Result<(Ifd, u64)> {
+        let (count_size, entry_size, offset_size) = if self.big { (8, 20, 8) } else { (2, 12, 4) };
+        self.check(offset, count_size)?;
+        let count = self.read_uint(offset, count_size)?;
+        if count > MAX_ENTRIES {
+            return Err(invalid_data("tiff", "too many directory entries"));
+        }
+        let entries_offset = offset + count_size;
+        self.check(entries_offset, count * entry_size + offset_size)?;
+        let mut raw_entries = vec![0u8; (count * entry_size) as usize];
+        self.reader.read_exact(&mut raw_entries)?;
+        let next = self.read_uint(entries_offset + count * entry_size, offset_size)?;
+
+        let mut entries = Vec::with_capacity(count as usize);
+        for raw in raw_entries.chunks(entry_size as usize) {
+            entries.push(self.read_entry(raw)?);
+        }
+        Ok((Ifd { entries }, next))
+    }
+
+    fn read_entry(&mut self, raw: &[u8]) -> std::io::Result<Entry> {
+        let tag = self.u16(&raw[0..2]);
+        let field_type = self.u16(&raw[2..4]);
+        let (count, inline) = if self.big {
+            (self.u64(&raw[4..12]), &raw[12..20])
+        } else {
+            (u64::from(self.u32(&raw[4..8])), &raw[8..12])
+        };
+        let type_size = match field_type {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 | 13 => 4,
+            5 | 10 | 12 | 16 | 17 | 18 => 8,
+            _ => {
+                return Ok(Entry {
+                    tag,
+                    value: Value::Skipped(field_type),
+                })
+            }
+        };
+        let length = count.saturating_mul(type_size);
+        let data = if length <= inline.len() as u64 {
+            inline[..length as usize].to_vec()
+        } else if length > MAX_VALUE_LENGTH {
+            return Ok(Entry {
+                tag,
+                value: Value::Skipped(field_type),
+            });
+        } else {
+            let offset = if self.big {
+                self.u64(inline)
+            } else {
+                u64::from(self.u32(inline))
+            };
+            self.check(offset, length)?;
+            let mut data = vec![0u8; length as usize];
+            self.reader.read_exact(&mut data)?;
+            data
+        };
+        Ok(Entry {
+            tag,
+            value: self.decode(field_type, &data),
+        })
+    }
+
+    fn decode(&self, field_type: u16, data: &[u8]) -> Value {
+        match field_type {
+            1 => Value::Byte(data.to_vec()),
+            2 => {
+                let text = data.split(|b| *b == 0).next().unwrap_or(&[]);
+                Value::Ascii(String::from_utf8_lossy(text).trim().to_owned())
+            }
+            3 => Value::Short(data.chunks(2).map(|v| self.u16(v)).collect()),
+            4 | 13 => Value::Long(data.chunks(4).map(|v| u64::from(self.u32(v))).collect()),
+            16 | 18 => Value::Long(data.chunks(8).map(|v| self.u64(v)).collect()),
+            5 => Value::Rational(
+                data.chunks(8)
+                    .map(|v| (self.u32(&v[..4]), self.u32(&v[4..])))
+                    .collect(),
+            ),
+            10 => Value::SignedRational(
+                data.chunks(8)
+                    .map(|v| (self.u32(&v[..4]) as i32, self.u32(&v[4..]) as i32))
+                    .collect(),
+            ),
+            7 => Value::Undefined(data.to_vec()),
+            other => Value::Skipped(other),
+        }
+    }
+
+    /// Ensures `length` bytes at `offset` are inside the structure and seeks there.
+    fn check(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        match offset.checked_add(length) {
+            Some(end) if end <= self.length => {
+                self.reader.seek(SeekFrom::Start(self.base + offset))?;
+                Ok(())
+            }
+            _ => Err(invalid_data("tiff", "offset outside of file")),
+        }
+    }
+
+    fn read_uint(&mut self, offset: u64, size: u64) -> std::io::Result<u64> {
+        self.reader.seek(SeekFrom::Start(self.base + offset))?;
+        let mut bytes = [0u8; 8];
+        self.reader.read_exact(&mut bytes[..size as usize])?;
+        Ok(match size {
+            2 => u64::from(self.u16(&bytes[..2])),
+            4 => u64::from(self.u32(&bytes[..4])),
+            _ => self.u64(&bytes),
+        })
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self.order {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self.order {
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn u64(&self, bytes: &[u8]) -> u64 {
+        let mut value = [0u8; 8];
+        value.copy_from_slice(&bytes[..8]);
+        match self.order {
+            ByteOrder::Little => u64::from_le_bytes(value),
+            ByteOrder::Big => u64::from_be_bytes(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::image::tiff::{
+        read_tiff, Entry, Ifd, Value, TAG_RESOLUTION_UNIT, TAG_X_RESOLUTION, TAG_Y_RESOLUTION,
+    };
+
+    fn entry(tag: u16, value: u32) -> Vec<u8> {
+        let mut entry = tag.to_le_bytes().to_vec();
+        entry.extend_from_slice(&[4, 0, 1, 0, 0, 0]);
+        entry.extend_from_slice(&value.to_le_bytes());
+        entry
+    }
+
+    #[test]
+    fn pages_without_reduced_resolutions() {
+        let mut data = b"II\x2a\0\x08\0\0\0".to_vec();
+        // (subfile type, width, height) of three IFDs, the second one is a thumbnail
+        let ifds = [(2, 100, 50), (1, 10, 5), (2, 200, 80)];
+        for (i, (subfile_type, width, height)) in ifds.iter().enumerate() {
+            data.extend_from_slice(&[3, 0]);
+            data.extend(entry(0x00fe, *subfile_type));
+            data.extend(entry(0x0100, *width));
+            data.extend(entry(0x0101, *height));
+            let next = if i + 1 < ifds.len() {
+                data.len() + 4
+            } else {
+                0
+            };
+            data.extend_from_slice(&(next as u32).to_le_bytes());
+        }
+        let tiff = read_tiff(&mut Cursor::new(data)).unwrap();
+        let sizes: Vec<_> = tiff.pages.iter().map(|p| (p.width, p.height)).collect();
+        assert_eq!(sizes, vec![(100, 50), (200, 80)]);
+    }
+
+    #[test]
+    fn resolution_in_inches_and_centimetres() {
+        let ifd = |unit: Option<u16>| {
+            let mut entries = vec![
+                Entry {
+                    tag: TAG_X_RESOLUTION,
+                    value: Value::Rational(vec![(300, 1)]),
+                },
+                Entry {
+                    tag: TAG_Y_RESOLUTION,
+                    value: Value::Rational(vec![(600, 2)]),
+                },
+            ];
+            if let Some(unit) = unit {
+                entries.push(Entry {
+                    tag: TAG_RESOLUTION_UNIT,
+                    value: Value::Short(vec![unit]),
+                });
+            }
+            Ifd { entries }
+        };
+        let per_metre = |unit| ifd(unit).resolution().map(|r| r.horizontal.round());
+        // inches are the default unit
+        assert_eq!(per_metre(None), Some(11811.0));
+        assert_eq!(per_metre(Some(2)), Some(11811.0));
+        assert_eq!(per_metre(Some(3)), Some(30000.0));
+        // no absolute unit
+        assert_eq!(per_metre(Some(1)), None);
+        assert_eq!(ifd(Some(3)).resolution().unwrap().vertical.round(), 30000.0);
+    }
+}
@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use crate::image::{invalid_data, read_vec};
+
+// Metadata chunks larger than this are skipped
+const MAX_METADATA_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// Dimensions and embedded metadata of a WebP file (RIFF container).
+#[derive(Debug, Default, PartialEq)]
+pub struct WebP {
+    pub width: u32,
+    pub height: u32,
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<String>,
+}
+
+impl WebP {
+    pub fn load(path: &PathBuf) -> std::io::Result<WebP> {
+        let mut file = File::open(path)?;
+        read_webp(&mut file)
+    }
+}
+
+/// Walks the RIFF chunks, reading only the frame headers and metadata chunks.
+pub fn read_webp<R: Read + Seek>(reader: &mut R) -> std::io::Result<WebP> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WEBP" {
+        return Err(invalid_data("webp", "missing RIFF/WEBP header"));
+    }
+    let riff_end = (u64::from(u32_le(&header[4..8])) + 8).min(file_length);
+
+    let mut webp = WebP::default();
+    let mut position = 12;
+    while position + 8 <= riff_end {
+        let mut chunk_header = [0u8; 8];
+        reader.read_exact(&mut chunk_header)?;
+        let length = u64::from(u32_le(&chunk_header[4..]));
+        position += 8;
+        if length > riff_end - position {
+            return Err(invalid_data("webp", "chunk exceeds file"));
+        }
+        match &chunk_header[..4] {
+            b"VP8X" if webp.width == 0 => {
+                let data = read_vec(reader, length.min(10))?;
+                if data.len() < 10 {
+                    return Err(invalid_data("webp", "short VP8X chunk"));
+                }
+                webp.width = u24_le(&data[4..7]) + 1;
+                webp.height = u24_le(&data[7..10]) + 1;
+            }
+            b"VP8 " if webp.width == 0 => {
+                let data = read_vec(reader, length.min(10))?;
+                if data.len() < 10 || data[3..6] != [0x9d, 0x01, 0x2a] {
+                    return Err(invalid_data("webp", "malformed VP8 frame header"));
+                }
+                webp.width = u32::from(u16::from_le_bytes([data[6], data[7]]) & 0x3fff);
+                webp.height = u32::from(u16::from_le_bytes([data[8], data[9]]) & 0x3fff);
+            }
+            b"VP8L" if webp.width == 0 => {
+                let data = read_vec(reader, length.min(5))?;
+                if data.len() < 5 || data[0] != 0x2f {
+                    return Err(invalid_data("webp", "malformed VP8L header"));
+                }
+                let bits = u32_le(&data[1..5]);
+                webp.width = (bits & 0x3fff) + 1;
+                webp.height = ((bits >> 14) & 0x3fff) + 1;
+            }
+            b"EXIF" if length <= MAX_METADATA_LENGTH => {
+                webp.exif = Some(read_vec(reader, length)?);
+            }
+            b"XMP " if length <= MAX_METADATA_LENGTH => {
+                let data = read_vec(reader, length)?;
+                webp.xmp = Some(String::from_utf8_lossy(&data).into_owned());
+            }
+            _ => (),
+        }
+        // chunks are padded to an even length
+        position += length + (length & 1);
+        reader.seek(SeekFrom::Start(position))?;
+    }
+    if webp.width == 0 {
+        return Err(invalid_data("webp", "missing image header"));
+    }
+    Ok(webp)
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn u24_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
@@ -0,0 +1,122 @@
+use crate::image::label::{Label, LabelValue};
+
+// Properties exposed as labels
+const PROPERTIES: &[&str] = &[
+    "dc:title",
+    "dc:description",
+    "dc:creator",
+    "dc:rights",
+    "dc:subject",
+    "xmp:CreateDate",
+    "xmpRights:UsageTerms",
+    "photoshop:Credit",
+];
+
+/// Extracts a few well-known properties from an XMP packet. This is not an
+/// RDF parser: it understands the simple forms writers use in practice,
+/// attributes (`dc:title="..."`) and elements with plain text or `rdf:li`
+/// items. Several items of a property become a list.
+pub fn labels(packet: &str) -> Vec<Label> {
+    let mut labels = Vec::new();
+    for property in PROPERTIES {
+        let mut values: Vec<LabelValue> = values(packet, property)
+            .iter()
+            .map(|value| LabelValue::parse(value))
+            .collect();
+        let value = match values.len() {
+            0 => continue,
+            1 => values.remove(0),
+            _ => LabelValue::List(values),
+        };
+        labels.push(Label::new(*property, value));
+    }
+    labels
+}
+
+fn values(packet: &str, property: &str) -> Vec<String> {
+    let mut values = Vec::new();
+
+    let attribute = format!("{}=\"", property);
+    if let Some(start) = packet.find(&attribute) {
+        let rest = &packet[start + attribute.len()..];
+        if let Some(end) = rest.find('"') {
+            values.push(unescape(&rest[..end]));
+        }
+    }
+
+    let open = format!("<{}", property);
+    let close = format!("</{}>", property);
+    if let Some(start) = packet.find(&open) {
+        let rest = &packet[start + open.len()..];
+        // skip attributes of the opening tag, the element may also be empty
+        let body_start = match rest.find('>') {
+            Some(position) if !rest[..position].ends_with('/') => position + 1,
+            _ => return values,
+        };
+        let body = match rest.find(&close) {
+            Some(end) if end >= body_start => &rest[body_start..end],
+            _ => return values,
+        };
+        if body.contains("<rdf:li") {
+            let mut items = body;
+            while let Some(item_start) = items.find("<rdf:li") {
+                let item = &items[item_start..];
+                let text_start = match item.find('>') {
+                    Some(position) => position + 1,
+                    None => break,
+                };
+                let text_end = match item.find("</rdf:li>") {
+                    Some(position) if position >= text_start => position,
+                    _ => break,
+                };
+                let text = item[text_start..text_end].trim();
+                if !text.is_empty() {
+                    values.push(unescape(text));
+                }
+                items = &item[text_end..];
+            }
+        } else if !body.trim().is_empty() && !body.contains('<') {
+            values.push(unescape(body.trim()));
+        }
+    }
+    values
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::label::{Label, LabelValue, Timestamp};
+    use crate::image::xmp::labels;
+
+    #[test]
+    fn reads_simple_properties() {
+        let packet = r#"<rdf:Description xmp:CreateDate="2020-05-01T10:00:00">
+            <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Watergate &amp; more</rdf:li></rdf:Alt></dc:title>
+            <dc:creator><rdf:Seq><rdf:li>A</rdf:li><rdf:li>B</rdf:li></rdf:Seq></dc:creator>
+        </rdf:Description>"#;
+        assert_eq!(
+            labels(packet),
+            vec![
+                Label::new("dc:title", LabelValue::Text("Watergate & more".to_owned())),
+                Label::new(
+                    "dc:creator",
+                    LabelValue::List(vec![
+                        LabelValue::Text("A".to_owned()),
+                        LabelValue::Text("B".to_owned())
+                    ])
+                ),
+                Label::new(
+                    "xmp:CreateDate",
+                    LabelValue::Timestamp(Timestamp::parse("2020-05-01T10:00:00").unwrap())
+                ),
+            ]
+        );
+    }
+}
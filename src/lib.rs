@@ -0,0 +1,15 @@
+#[macro_use]
+extern crate actix_web;
+
+pub mod av;
+pub mod cache;
+pub mod config;
+pub mod cors;
+pub mod error;
+pub mod http_api;
+pub mod iiif;
+pub mod image;
+pub mod meta;
+pub mod proxy;
+pub mod validator;
+pub mod watch;
@@ -1,18 +1,14 @@
-#[macro_use]
-extern crate actix_web;
-
-mod config;
-mod http_api;
-mod iiif;
-mod image;
-mod meta;
-
 use clap;
 
-use crate::iiif::IiifGenerator;
-use crate::image::source::ImageSource;
+use iiif_forager::cache::Cache;
+use iiif_forager::cors::Cors;
+use iiif_forager::http_api;
+use iiif_forager::iiif::IiifGenerator;
+use iiif_forager::image::source::ImageSource;
+use iiif_forager::proxy::Proxy;
+use iiif_forager::watch::{directory_ids, Watcher};
 
-use crate::config::Config;
+use iiif_forager::config::Config;
 use std::path::Path;
 
 fn main() {
@@ -26,26 +22,85 @@ fn main() {
                 .required(true)
                 .takes_value(true),
         )
+        .subcommand(
+            clap::SubCommand::with_name("index")
+                .about("Rebuild the image index from all files, then exit (stop the server first)"),
+        )
         .get_matches();
 
     let config_path = Path::new(matches.value_of("CONFIG").unwrap());
     let config = match Config::load(config_path) {
         Ok(config) => config,
-        Err(e) => {
-            println!("Could not load config, exiting: {}", e);
-            return;
-        }
+        Err(e) => exit(&format!("Could not load config: {}", e)),
     };
 
     if !config.serving.path.exists() {
-        panic!("path {} does not exist", config.serving.path.display());
+        exit(&format!(
+            "Path {} does not exist",
+            config.serving.path.display()
+        ));
     }
     if !config.serving.path.is_dir() {
-        panic!("path {} is not a directory", config.serving.path.display());
+        exit(&format!(
+            "Path {} is not a directory",
+            config.serving.path.display()
+        ));
+    }
+
+    if matches.subcommand_matches("index").is_some() {
+        rebuild_index(config);
+        return;
     }
 
     let bind = config.serving.bind();
+    let documents = Cache::new(&config.cache);
+    let watcher = Watcher::new(&config);
+    let cors = Cors::new(&config.cors);
+    let proxy = Proxy::new(&config);
     let image_source = ImageSource::new(config.clone());
     let manifest_generator = IiifGenerator::new(config);
-    http_api::start(manifest_generator, image_source, bind).unwrap()
+    let started = http_api::start(
+        manifest_generator,
+        image_source,
+        documents,
+        watcher,
+        cors,
+        proxy,
+        bind.clone(),
+    );
+    if let Err(e) = started {
+        exit(&format!("Could not serve on {}: {}", bind, e));
+    }
+}
+
+/// Reports a startup error and ends the process with a failure status.
+fn exit(message: &str) -> ! {
+    eprintln!("{}, exiting", message);
+    std::process::exit(1)
+}
+
+/// Reads every directory into a new index. The old file is deleted, a
+/// server still using it would keep writing to the deleted file.
+fn rebuild_index(config: Config) {
+    let index_path = match &config.images.index {
+        Some(path) => path.clone(),
+        None => exit("No index configured (images: index)"),
+    };
+    if index_path.exists() {
+        if let Err(e) = std::fs::remove_file(&index_path) {
+            exit(&format!("Could not remove {}: {}", index_path.display(), e));
+        }
+    }
+    let mut ids = directory_ids(&config.serving.path, &config.urls);
+    ids.insert(0, String::new());
+    let image_source = ImageSource::new(config);
+    if !image_source.has_index() {
+        exit(&format!("Could not create {}", index_path.display()));
+    }
+    for id in &ids {
+        match image_source.load(id) {
+            Ok(images) => println!("Indexed /{}: {} images", id, images.len()),
+            Err(e) => println!("Could not index /{}: {}", id, e),
+        }
+    }
 }
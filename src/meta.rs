@@ -1,12 +1,18 @@
+use crate::config::Mapping;
+use crate::error;
 use crate::iiif::metadata::Metadata;
 use serde::Deserialize;
 use serde_json;
 use serde_yaml;
 use std::error::Error;
 use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
-enum Format {
+/// Sidecar file names, the first one found is used
+pub const FILENAMES: &[&str] = &["meta.json", "meta.yml", "meta.yaml"];
+
+pub enum Format {
     JSON,
     YAML,
 }
@@ -18,40 +24,50 @@ pub struct Meta {
     pub description: Option<String>,
     #[serde(default = "Vec::new")]
     pub metadata: Vec<Metadata>,
+    /// Scan resolution in pixels per inch, overrides the one in the images
+    pub dpi: Option<f64>,
+    /// Overrides the mapping of embedded labels in the configuration
+    #[serde(default)]
+    pub mapping: Mapping,
 }
 
 impl Meta {
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Meta, Box<dyn Error>> {
-        for (filename, format) in vec![
-            ("meta.json", Format::JSON),
-            ("meta.yml", Format::YAML),
-            ("meta.yaml", Format::YAML),
-        ] {
+    /// The sidecar of a directory, empty if there is none.
+    pub fn load<P: AsRef<Path>>(path: P) -> error::Result<Meta> {
+        for filename in FILENAMES {
+            let format = if filename.ends_with(".json") {
+                Format::JSON
+            } else {
+                Format::YAML
+            };
             let meta_path = path.as_ref().join(filename);
             if !meta_path.exists() {
                 continue;
             }
-            let json_file = File::open(meta_path)?;
-            let context = match format {
-                Format::JSON => serde_json::from_reader(&json_file)?,
-                Format::YAML => serde_yaml::from_reader(&json_file)?,
+            let json_file = match File::open(&meta_path) {
+                Ok(file) => file,
+                Err(e) => return Err(error::Error::UnreadableFile(meta_path, e)),
             };
-            return Ok(context);
+            return Meta::from_reader(&json_file, format)
+                .map_err(|e| error::Error::InvalidSidecar(meta_path, e.to_string()));
         }
         Ok(Meta::empty())
     }
 
-    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Meta {
-        match Meta::load(path) {
-            Ok(meta) => meta,
-            Err(_) => Meta::empty(),
-        }
+    pub fn from_reader<R: Read>(reader: R, format: Format) -> Result<Meta, Box<dyn Error>> {
+        let context = match format {
+            Format::JSON => serde_json::from_reader(reader)?,
+            Format::YAML => serde_yaml::from_reader(reader)?,
+        };
+        Ok(context)
     }
 
-    pub const fn empty() -> Meta {
+    pub fn empty() -> Meta {
         Meta {
             description: None,
             metadata: Vec::new(),
+            dpi: None,
+            mapping: Mapping::default(),
         }
     }
 }
@@ -61,12 +77,15 @@ mod tests {
 
     use crate::iiif::metadata::{LocalizedValue, Metadata};
     use crate::meta::Meta;
+    use std::collections::BTreeMap;
 
     #[test]
     fn load_json() {
         let json = r#"
         {
             "description": "Expected description",
+            "dpi": 600,
+            "mapping": {"label": ["Title"], "rename": {"Author": "Creator"}},
             "metadata": [
                 {
                     "label": "size",
@@ -79,11 +98,18 @@ mod tests {
                 {
                     "label": "quality",
                     "value": [{"@value": "high", "@language": "en"}]
+                },
+                {
+                    "label": {"en": ["place"], "de": ["Ort"]},
+                    "value": {"en": ["harbour"], "de": ["Hafen"]}
                 }
             ]
         }"#;
         let actual: Meta = serde_json::from_str(json).unwrap();
         assert_eq!(actual.description, Some("Expected description".to_owned()));
+        assert_eq!(actual.dpi, Some(600.0));
+        assert_eq!(actual.mapping.label, vec!["Title".to_owned()]);
+        assert_eq!(actual.mapping.rename["Author"], "Creator");
         assert_eq!(actual.metadata[0], Metadata::key_value("size", "53 MB"));
         assert_eq!(
             actual.metadata[1],
@@ -96,5 +122,18 @@ mod tests {
             actual.metadata[2],
             Metadata::localized("quality", vec![LocalizedValue::new("high", "en")])
         );
+        let language_map = |en: &str, de: &str| {
+            let mut map = BTreeMap::new();
+            map.insert("en".to_owned(), vec![en.to_owned()]);
+            map.insert("de".to_owned(), vec![de.to_owned()]);
+            map
+        };
+        assert_eq!(
+            actual.metadata[3],
+            Metadata::language_map(
+                language_map("place", "Ort"),
+                language_map("harbour", "Hafen")
+            )
+        );
     }
 }
@@ -0,0 +1,323 @@
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::HttpRequest;
+
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use crate::config::{Config, Urls};
+
+// Forwarded URLs remembered for prewarming, e.g. one per public host name
+const MAX_VARIANTS: usize = 4;
+
+/// Public URLs of requests that come through a trusted reverse proxy.
+pub struct Proxy {
+    trusted: Vec<IpAddr>,
+    image_api: bool,
+    urls: Urls,
+    /// Forwarded URLs of recent requests, most recent last
+    recent: Mutex<Vec<Urls>>,
+}
+
+/// The original request as told by a proxy. Invalid values are left out.
+#[derive(Debug, Default, PartialEq)]
+struct Forwarded {
+    proto: Option<String>,
+    host: Option<String>,
+    prefix: Option<String>,
+}
+
+impl Proxy {
+    pub fn new(config: &Config) -> Proxy {
+        Proxy {
+            trusted: config.proxy.trusted.clone(),
+            image_api: config.proxy.image_api,
+            urls: config.urls.clone(),
+            recent: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// URLs for the ids in documents for a request, None if the configured
+    /// ones are used: the request did not come from a trusted proxy or has
+    /// no forwarded headers.
+    pub fn urls(&self, request: &HttpRequest) -> Option<Urls> {
+        let peer = request.peer_addr()?.ip();
+        let urls = self.urls_from(peer, request.headers())?;
+        self.remember(&urls);
+        Some(urls)
+    }
+
+    /// Forwarded URLs seen recently, so documents can be generated and
+    /// dropped for them as well as for the configured ones.
+    pub fn recent(&self) -> Vec<Urls> {
+        self.recent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn remember(&self, urls: &Urls) {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.retain(|seen| seen != urls);
+        recent.push(urls.clone());
+        if recent.len() > MAX_VARIANTS {
+            recent.remove(0);
+        }
+    }
+
+    fn urls_from(&self, peer: IpAddr, headers: &HeaderMap) -> Option<Urls> {
+        if !self.trusted.contains(&peer) {
+            return None;
+        }
+        let forwarded = Forwarded::from_headers(headers);
+        if forwarded == Forwarded::default() {
+            return None;
+        }
+        let mut urls = self.urls.clone();
+        urls.presentation_api = forwarded.apply(&self.urls.presentation_api, true);
+        if self.image_api {
+            // the prefix belongs to forager, not to the image server
+            urls.image_api = forwarded.apply(&self.urls.image_api, false);
+        }
+        Some(urls)
+    }
+}
+
+impl Forwarded {
+    /// Reads `Forwarded` (RFC 7239), then `X-Forwarded-Proto` and
+    /// `X-Forwarded-Host` for what it lacks, and `X-Forwarded-Prefix`. Only
+    /// the last value counts: proxies append theirs to the list or as
+    /// another header line, so earlier ones may come from the client.
+    fn from_headers(headers: &HeaderMap) -> Forwarded {
+        let last = |name: &str| -> Option<String> {
+            let value = last_line(headers, name)?.to_str().ok()?;
+            Some(value.rsplit(',').next()?.trim().to_owned())
+        };
+        let mut forwarded = Forwarded::default();
+        if let Some(element) = last("forwarded") {
+            for pair in element.split(';') {
+                let (name, value) = match pair.split_once('=') {
+                    Some((name, value)) => (name.trim(), value.trim().trim_matches('"')),
+                    None => continue,
+                };
+                if name.eq_ignore_ascii_case("proto") {
+                    forwarded.proto = valid_proto(value);
+                } else if name.eq_ignore_ascii_case("host") {
+                    forwarded.host = valid_host(value);
+                }
+            }
+        }
+        if forwarded.proto.is_none() {
+            forwarded.proto = last("x-forwarded-proto").and_then(|proto| valid_proto(&proto));
+        }
+        if forwarded.host.is_none() {
+            forwarded.host = last("x-forwarded-host").and_then(|host| valid_host(&host));
+        }
+        forwarded.prefix = last("x-forwarded-prefix").and_then(|prefix| valid_prefix(&prefix));
+        forwarded
+    }
+
+    /// A configured base URL with the forwarded scheme, host and, with
+    /// `with_prefix`, path.
+    fn apply(&self, base: &str, with_prefix: bool) -> String {
+        let (scheme, rest) = match base.split_once("://") {
+            Some(parts) => parts,
+            None => return base.to_owned(),
+        };
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let path = match &self.prefix {
+            Some(prefix) if with_prefix => prefix.as_str(),
+            _ => path,
+        };
+        format!(
+            "{}://{}{}",
+            self.proto.as_deref().unwrap_or(scheme),
+            self.host.as_deref().unwrap_or(authority),
+            path
+        )
+    }
+}
+
+/// The header line that was added last. The `HeaderMap` of actix-http 1.0
+/// puts the second line in front of the first when it is appended, the
+/// lines after that are kept in order.
+fn last_line<'h>(headers: &'h HeaderMap, name: &str) -> Option<&'h HeaderValue> {
+    let lines: Vec<_> = headers.get_all(name).collect();
+    match lines.len() {
+        2 => lines.first().copied(),
+        _ => lines.last().copied(),
+    }
+}
+
+fn valid_proto(proto: &str) -> Option<String> {
+    let proto = proto.to_ascii_lowercase();
+    if proto == "http" || proto == "https" {
+        Some(proto)
+    } else {
+        None
+    }
+}
+
+/// A host name or address with an optional port.
+fn valid_host(host: &str) -> Option<String> {
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'));
+    if valid {
+        Some(host.to_owned())
+    } else {
+        None
+    }
+}
+
+/// A path starting with `/`, without the trailing one.
+fn valid_prefix(prefix: &str) -> Option<String> {
+    let valid = prefix.starts_with('/')
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !matches!(c, '"' | '<' | '>' | '\\' | '?' | '#'));
+    if valid {
+        Some(prefix.trim_end_matches('/').to_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, Proxy as ProxyConfig, Urls};
+    use crate::proxy::Proxy;
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use std::net::IpAddr;
+
+    fn headers(entries: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn builds_urls_from_trusted_proxies() {
+        let proxy_address: IpAddr = "10.0.0.5".parse().unwrap();
+        let config: Config = serde_yaml::from_str(
+            "
+            serving: {path: samples, host: localhost, port: 7890}
+            urls:
+                path sep: '-'
+                image api: http://localhost:8182/iiif/2
+                presentation api: http://localhost:7890
+            ",
+        )
+        .unwrap();
+        let urls = |image_api: bool, peer: &str, entries| -> Option<Urls> {
+            let config = Config {
+                proxy: ProxyConfig {
+                    trusted: vec![proxy_address],
+                    image_api,
+                },
+                ..config.clone()
+            };
+            Proxy::new(&config).urls_from(peer.parse().unwrap(), &headers(entries))
+        };
+
+        let forwarded = urls(
+            false,
+            "10.0.0.5",
+            &[
+                // the client sent the first value, the proxy added the last
+                ("x-forwarded-host", "evil.example.org, iiif.example.org"),
+                ("x-forwarded-proto", "https"),
+                ("x-forwarded-prefix", "/presentation/"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            forwarded.presentation_api,
+            "https://iiif.example.org/presentation"
+        );
+        assert_eq!(forwarded.image_api, "http://localhost:8182/iiif/2");
+
+        let forwarded = urls(
+            true,
+            "10.0.0.5",
+            &[
+                (
+                    "forwarded",
+                    "for=192.0.2.1;host=evil.example.org, \
+                     for=198.51.100.7;proto=https;host=\"iiif.example.org\"",
+                ),
+                ("x-forwarded-host", "ignored.example.org"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(forwarded.presentation_api, "https://iiif.example.org");
+        assert_eq!(forwarded.image_api, "https://iiif.example.org/iiif/2");
+
+        // the proxy added its own header line after the client's
+        let forwarded = urls(
+            false,
+            "10.0.0.5",
+            &[
+                ("x-forwarded-host", "evil.example.org"),
+                ("x-forwarded-host", "iiif.example.org"),
+                ("forwarded", "host=evil.example.org"),
+                ("forwarded", "proto=https"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(forwarded.presentation_api, "https://iiif.example.org");
+        let forwarded = urls(
+            false,
+            "10.0.0.5",
+            &[
+                ("x-forwarded-host", "evil.example.org"),
+                ("x-forwarded-host", "other.example.org"),
+                ("x-forwarded-host", "iiif.example.org"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(forwarded.presentation_api, "http://iiif.example.org");
+
+        // untrusted, without headers, or with invalid values
+        let spoofed = [("x-forwarded-host", "evil.example.org")];
+        assert!(urls(false, "192.0.2.1", &spoofed).is_none());
+        assert!(urls(false, "10.0.0.5", &[]).is_none());
+        assert!(urls(false, "10.0.0.5", &[("x-forwarded-host", "a/b")]).is_none());
+    }
+
+    #[test]
+    fn remembers_recent_variants() {
+        let config: Config = serde_yaml::from_str(
+            "
+            serving: {path: samples, host: localhost, port: 7890}
+            urls:
+                path sep: '-'
+                image api: http://localhost:8182/iiif/2
+                presentation api: http://localhost:7890
+            ",
+        )
+        .unwrap();
+        let proxy = Proxy::new(&config);
+        let host = |name: &str| Urls {
+            presentation_api: format!("https://{}", name),
+            ..config.urls.clone()
+        };
+        for name in &["a", "b", "a", "c", "d", "e"] {
+            proxy.remember(&host(name));
+        }
+        let hosts: Vec<_> = proxy
+            .recent()
+            .into_iter()
+            .map(|urls| urls.presentation_api)
+            .collect();
+        assert_eq!(
+            hosts,
+            vec!["https://a", "https://c", "https://d", "https://e"]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch,
+    LastModified,
+};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::meta;
+
+/// Identifies a version of a generated document without generating it, so
+/// unchanged documents can be answered with 304 Not Modified.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validator {
+    pub tag: String,
+    pub modified: SystemTime,
+}
+
+impl Validator {
+    /// Validator of a manifest: names, sizes and modification times of the
+    /// files in `directory` and the contents of the sidecar. `salt` covers
+    /// everything else the document depends on, like the configuration.
+    pub fn for_manifest(directory: &Path, salt: &str) -> std::io::Result<Validator> {
+        let mut hasher = DefaultHasher::new();
+        salt.hash(&mut hasher);
+        let mut modified = directory.metadata()?.modified()?;
+        let mut entries: Vec<_> = std::fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok())
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            let file_modified = metadata.modified()?;
+            modified = modified.max(file_modified);
+            entry.file_name().hash(&mut hasher);
+            metadata.len().hash(&mut hasher);
+            since_epoch(file_modified).hash(&mut hasher);
+        }
+        // sidecars are small and often edited within the same second
+        for filename in meta::FILENAMES {
+            if let Ok(contents) = std::fs::read(directory.join(filename)) {
+                contents.hash(&mut hasher);
+            }
+        }
+        Ok(Validator::new(hasher, modified))
+    }
+
+    /// Validator of a collection: the names of the subdirectories.
+    pub fn for_collection(directory: &Path, salt: &str) -> std::io::Result<Validator> {
+        let mut hasher = DefaultHasher::new();
+        salt.hash(&mut hasher);
+        let modified = directory.metadata()?.modified()?;
+        let mut names: Vec<_> = std::fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name())
+            .collect();
+        names.sort();
+        names.hash(&mut hasher);
+        Ok(Validator::new(hasher, modified))
+    }
+
+    /// Validator of a variant of the same document, e.g. with other URLs.
+    pub fn vary(&self, variant: &str) -> Validator {
+        let mut hasher = DefaultHasher::new();
+        self.tag.hash(&mut hasher);
+        variant.hash(&mut hasher);
+        Validator::new(hasher, self.modified)
+    }
+
+    fn new(hasher: DefaultHasher, modified: SystemTime) -> Validator {
+        Validator {
+            tag: format!("{:016x}", hasher.finish()),
+            modified,
+        }
+    }
+
+    /// True if the client already has this version, after `If-None-Match`
+    /// or, without it, `If-Modified-Since`.
+    pub fn is_fresh(&self, request: &HttpRequest) -> bool {
+        let etag = EntityTag::strong(self.tag.clone());
+        match request.get_header::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => return true,
+            Some(IfNoneMatch::Items(tags)) => return tags.iter().any(|tag| tag.weak_eq(&etag)),
+            None => (),
+        }
+        match request.get_header::<IfModifiedSince>() {
+            // HTTP dates have no fractions of seconds
+            Some(IfModifiedSince(since)) => {
+                since_epoch(self.modified).as_secs() <= since_epoch(since.into()).as_secs()
+            }
+            None => false,
+        }
+    }
+
+    /// Adds `ETag`, `Last-Modified` and `Cache-Control` to a response.
+    pub fn headers<'r>(
+        &self,
+        response: &'r mut HttpResponseBuilder,
+    ) -> &'r mut HttpResponseBuilder {
+        response
+            .set(ETag(EntityTag::strong(self.tag.clone())))
+            .set(LastModified(HttpDate::from(self.modified)))
+            // directories change at any time, so clients have to ask
+            .set(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::NoCache,
+            ]))
+    }
+
+    pub fn not_modified(&self) -> HttpResponse {
+        self.headers(&mut HttpResponse::NotModified()).finish()
+    }
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::validator::Validator;
+    use actix_web::http::header;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn changes_with_files_and_answers_conditional_requests() {
+        let directory =
+            std::env::temp_dir().join(format!("forager-validator-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("a.png"), b"first").unwrap();
+        let first = Validator::for_manifest(&directory, "config").unwrap();
+        assert_eq!(
+            first,
+            Validator::for_manifest(&directory, "config").unwrap()
+        );
+        assert_ne!(first, Validator::for_manifest(&directory, "other").unwrap());
+        std::fs::write(directory.join("meta.yml"), b"dpi: 300").unwrap();
+        let second = Validator::for_manifest(&directory, "config").unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_ne!(first.tag, second.tag);
+
+        let request =
+            TestRequest::with_header(header::IF_NONE_MATCH, format!("\"{}\"", second.tag))
+                .to_http_request();
+        assert!(second.is_fresh(&request));
+        assert!(!first.is_fresh(&request));
+        let request = TestRequest::with_header(header::IF_NONE_MATCH, "\"other\"")
+            .header(header::IF_MODIFIED_SINCE, "Fri, 01 Jan 2100 00:00:00 GMT")
+            .to_http_request();
+        assert!(!second.is_fresh(&request));
+        let request =
+            TestRequest::with_header(header::IF_MODIFIED_SINCE, "Fri, 01 Jan 2100 00:00:00 GMT")
+                .to_http_request();
+        assert!(second.is_fresh(&request));
+        assert!(!second.is_fresh(&TestRequest::default().to_http_request()));
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Files count once they are closed after writing, not with every write
+const MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_ATTRIB
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_ONLYDIR
+    | libc::IN_DONT_FOLLOW;
+// Fixed part of struct inotify_event: wd, mask, cookie and len
+const EVENT_HEADER_LENGTH: usize = 16;
+const BUFFER_LENGTH: usize = 64 * 1024;
+
+/// Change notifications for directories, from the Linux kernel.
+pub struct Inotify {
+    fd: libc::c_int,
+    /// Watched directories with their ids, by watch descriptor
+    watches: HashMap<libc::c_int, (PathBuf, String)>,
+}
+
+/// Something happened to an entry of a watched directory.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// A file or directory in the directory with this id was added, changed
+    /// or removed
+    Changed {
+        directory: PathBuf,
+        id: String,
+        name: OsString,
+        is_dir: bool,
+    },
+    /// Events were lost, everything has to be looked at again
+    Overflow,
+}
+
+impl Inotify {
+    pub fn new() -> io::Result<Inotify> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify {
+            fd,
+            watches: HashMap::new(),
+        })
+    }
+
+    /// Watches a directory, but not its subdirectories. Fails with "No space
+    /// left on device" when `fs.inotify.max_user_watches` is reached.
+    pub fn add(&mut self, directory: &Path, id: &str) -> io::Result<()> {
+        let path = CString::new(directory.as_os_str().as_bytes())?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.watches
+            .insert(wd, (directory.to_owned(), id.to_owned()));
+        Ok(())
+    }
+
+    /// Stops watching a directory and everything below it, e.g. after it
+    /// was moved away.
+    pub fn remove_below(&mut self, directory: &Path) {
+        let below: Vec<_> = self
+            .watches
+            .iter()
+            .filter(|(_, (path, _))| path.starts_with(directory))
+            .map(|(wd, _)| *wd)
+            .collect();
+        for wd in below {
+            unsafe { libc::inotify_rm_watch(self.fd, wd) };
+            self.watches.remove(&wd);
+        }
+    }
+
+    /// Waits for events, up to `timeout` if given. No events if it elapsed.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<Event>> {
+        let mut poll = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis() as libc::c_int);
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(e),
+            };
+        }
+        if ready == 0 {
+            return Ok(Vec::new());
+        }
+        let mut buffer = vec![0u8; BUFFER_LENGTH];
+        let read = unsafe {
+            libc::read(
+                self.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buffer.truncate(read as usize);
+        Ok(self.parse(&buffer))
+    }
+
+    fn parse(&mut self, mut buffer: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        while buffer.len() >= EVENT_HEADER_LENGTH {
+            let field = |at: usize| [buffer[at], buffer[at + 1], buffer[at + 2], buffer[at + 3]];
+            let wd = libc::c_int::from_ne_bytes(field(0));
+            let mask = u32::from_ne_bytes(field(4));
+            let length = u32::from_ne_bytes(field(12)) as usize;
+            let end = (EVENT_HEADER_LENGTH + length).min(buffer.len());
+            // the name is padded with NUL bytes
+            let name = &buffer[EVENT_HEADER_LENGTH..end];
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+            buffer = &buffer[end..];
+
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                events.push(Event::Overflow);
+            } else if mask & libc::IN_IGNORED != 0 {
+                // the directory is gone, the kernel removed the watch
+                self.watches.remove(&wd);
+            } else if let Some((directory, id)) = self.watches.get(&wd) {
+                events.push(Event::Changed {
+                    directory: directory.clone(),
+                    id: id.clone(),
+                    name: OsStr::from_bytes(name).to_os_string(),
+                    is_dir: mask & libc::IN_ISDIR != 0,
+                });
+            }
+        }
+        events
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::watch::inotify::{Event, Inotify};
+    use std::time::Duration;
+
+    #[test]
+    fn reports_changed_entries() {
+        let root = std::env::temp_dir().join(format!("forager-inotify-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let mut inotify = Inotify::new().unwrap();
+        inotify.add(&root, "a").unwrap();
+        std::fs::write(root.join("scan.png"), b"png").unwrap();
+        std::fs::create_dir(root.join("b")).unwrap();
+        let mut events = Vec::new();
+        loop {
+            let more = inotify.wait(Some(Duration::from_millis(200))).unwrap();
+            if more.is_empty() {
+                break;
+            }
+            events.extend(more);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let changed = |name: &str, is_dir| Event::Changed {
+            directory: root.clone(),
+            id: "a".to_owned(),
+            name: name.into(),
+            is_dir,
+        };
+        assert!(events.contains(&changed("scan.png", false)), "{:?}", events);
+        assert!(events.contains(&changed("b", true)), "{:?}", events);
+    }
+}
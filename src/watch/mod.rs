@@ -0,0 +1,416 @@
+use actix_web::web;
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{Config, Urls, WatchMode};
+use crate::http_api::{collection_json, document_key, manifest_json, variant, Documents};
+use crate::iiif::IiifGenerator;
+use crate::image::source::ImageSource;
+use crate::proxy::Proxy;
+
+#[cfg(target_os = "linux")]
+mod inotify;
+
+// Directories nested deeper are not watched
+const MAX_DEPTH: usize = 32;
+// Events are collected until none came for this long, so a batch of copied
+// files is handled at once
+#[cfg(target_os = "linux")]
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// Watches the directories below `serving.path` for changes. Changed
+/// directories are found by their manifest validators, so this notices
+/// the same changes as conditional requests do: added, removed or
+/// modified files and edited sidecars. Notifications tell which
+/// directories to look at, polling looks at all of them, which works on
+/// every platform and on network file systems.
+pub struct Watcher {
+    root: PathBuf,
+    urls: Urls,
+    mode: WatchMode,
+    interval: Duration,
+    prewarm: bool,
+    /// Manifest validator tags by directory id
+    seen: HashMap<String, String>,
+}
+
+/// What the server shares with the watcher.
+struct Services {
+    image_source: web::Data<ImageSource>,
+    iiif_generator: web::Data<IiifGenerator>,
+    documents: web::Data<Documents>,
+    proxy: web::Data<Proxy>,
+}
+
+impl Services {
+    /// The configured URLs and those of recent forwarded requests, as the
+    /// cached documents may have been made with any of them.
+    fn variants(&self) -> Vec<Option<Urls>> {
+        let mut variants = vec![None];
+        variants.extend(self.proxy.recent().into_iter().map(Some));
+        variants
+    }
+}
+
+impl Watcher {
+    /// None if watching is turned off.
+    pub fn new(config: &Config) -> Option<Watcher> {
+        if config.watch.mode == WatchMode::Off {
+            return None;
+        }
+        Some(Watcher {
+            root: config.serving.path.clone(),
+            urls: config.urls.clone(),
+            mode: config.watch.mode,
+            interval: Duration::from_secs(config.watch.interval.max(1)),
+            prewarm: config.watch.prewarm,
+            seen: HashMap::new(),
+        })
+    }
+
+    /// Watches in a background thread until the process ends.
+    pub fn spawn(
+        mut self,
+        image_source: web::Data<ImageSource>,
+        iiif_generator: web::Data<IiifGenerator>,
+        documents: web::Data<Documents>,
+        proxy: web::Data<Proxy>,
+    ) {
+        let services = Services {
+            image_source,
+            iiif_generator,
+            documents,
+            proxy,
+        };
+        std::thread::spawn(move || {
+            self.seen = self.scan(&services.image_source);
+            if self.prewarm {
+                for id in self.seen.keys() {
+                    warm_manifest(id, None, &services);
+                }
+            }
+            if self.mode == WatchMode::Notify {
+                if let Err(e) = self.notify(&services) {
+                    println!("Could not get change notifications: {}", e);
+                }
+            }
+            println!(
+                "Watching {} every {}s",
+                self.root.display(),
+                self.interval.as_secs()
+            );
+            loop {
+                std::thread::sleep(self.interval);
+                self.poll(&services);
+            }
+        });
+    }
+
+    /// Looks at the directories that notifications name. Only returns if
+    /// notifications cannot be used.
+    #[cfg(target_os = "linux")]
+    fn notify(&mut self, services: &Services) -> std::io::Result<()> {
+        use inotify::{Event, Inotify};
+
+        let mut inotify = Inotify::new()?;
+        let root = self.root.clone();
+        self.watch_below(&mut inotify, &root, "")?;
+        println!("Watching {} with change notifications", root.display());
+        loop {
+            let mut events = inotify.wait(None)?;
+            loop {
+                let more = inotify.wait(Some(SETTLE))?;
+                if more.is_empty() {
+                    break;
+                }
+                events.extend(more);
+            }
+            if events.contains(&Event::Overflow) {
+                println!("Missed change notifications, looking at all directories");
+                inotify = Inotify::new()?;
+                self.watch_below(&mut inotify, &root, "")?;
+                self.poll(services);
+                continue;
+            }
+            let mut ids = BTreeSet::new();
+            for event in events {
+                let (directory, id, name, is_dir) = match event {
+                    Event::Changed {
+                        directory,
+                        id,
+                        name,
+                        is_dir,
+                    } => (directory, id, name, is_dir),
+                    Event::Overflow => continue,
+                };
+                let name = match name.into_string() {
+                    Ok(name) if !(is_dir && name.starts_with('.')) => name,
+                    _ => continue,
+                };
+                if is_dir {
+                    // gone or moved away: it and everything below
+                    let path = directory.join(&name);
+                    let child = self.urls.join(&id, &self.urls.escape(&name));
+                    inotify.remove_below(&path);
+                    ids.extend(self.seen_below(&child));
+                    // new or moved here
+                    if path.is_dir() {
+                        ids.extend(self.watch_below(&mut inotify, &path, &child)?);
+                    }
+                }
+                ids.insert(id);
+            }
+            for id in ids.into_iter().filter(|id| !id.is_empty()) {
+                let tag = services
+                    .image_source
+                    .manifest_validator(&id)
+                    .ok()
+                    .map(|validator| validator.tag);
+                self.update(&id, tag, services);
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn notify(&mut self, _services: &Services) -> std::io::Result<()> {
+        Err(std::io::Error::other("only available on Linux"))
+    }
+
+    /// Watches a directory and all directories below it, returns their ids.
+    #[cfg(target_os = "linux")]
+    fn watch_below(
+        &self,
+        inotify: &mut inotify::Inotify,
+        path: &Path,
+        id: &str,
+    ) -> std::io::Result<Vec<String>> {
+        let depth = path
+            .strip_prefix(&self.root)
+            .map_or(0, |below| below.components().count());
+        let mut found = vec![(id.to_owned(), path.to_owned())];
+        collect_directories(path, &self.urls, id, depth, &mut found);
+        for (id, path) in &found {
+            match inotify.add(path, id) {
+                // removed in the meantime
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                result => result?,
+            }
+        }
+        Ok(found.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// A directory and the directories below it that were seen before.
+    #[cfg(target_os = "linux")]
+    fn seen_below(&self, id: &str) -> Vec<String> {
+        let prefix = format!("{}{}", id, self.urls.path_sep);
+        self.seen
+            .keys()
+            .filter(|seen| *seen == id || seen.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn poll(&mut self, services: &Services) {
+        let current = self.scan(&services.image_source);
+        for id in changes(&self.seen, &current) {
+            let tag = current.get(&id).cloned();
+            self.update(&id, tag, services);
+        }
+    }
+
+    /// Drops the documents of a directory if its manifest validator tag
+    /// changed, None if the directory is gone, for every variant of URLs.
+    /// With `prewarm`, they are generated again.
+    fn update(&mut self, id: &str, tag: Option<String>, services: &Services) {
+        let before = self.seen.get(id).cloned();
+        if before == tag {
+            return;
+        }
+        println!("Changed: {}", id);
+        let added_or_removed = before.is_some() != tag.is_some();
+        let variants = services.variants();
+        for urls in &variants {
+            let documents = &services.documents;
+            documents.remove(&document_key(id, "manifest", urls.as_ref()));
+            if added_or_removed {
+                documents.remove(&document_key(id, "collection", urls.as_ref()));
+                documents.remove(&document_key(self.parent(id), "collection", urls.as_ref()));
+            }
+        }
+        let exists = tag.is_some();
+        match tag {
+            Some(tag) => self.seen.insert(id.to_owned(), tag),
+            None => self.seen.remove(id),
+        };
+        if !self.prewarm {
+            return;
+        }
+        for urls in &variants {
+            if exists {
+                warm_manifest(id, urls.as_ref(), services);
+            }
+            if added_or_removed {
+                warm_collection(self.parent(id), urls.as_ref(), services);
+            }
+        }
+    }
+
+    /// Manifest validator tags of all directories.
+    fn scan(&self, image_source: &ImageSource) -> HashMap<String, String> {
+        let mut tags = HashMap::new();
+        for id in self.ids() {
+            if let Ok(validator) = image_source.manifest_validator(&id) {
+                tags.insert(id, validator.tag);
+            }
+        }
+        tags
+    }
+
+    fn ids(&self) -> Vec<String> {
+        directory_ids(&self.root, &self.urls)
+    }
+
+    fn parent<'a>(&self, id: &'a str) -> &'a str {
+        // names in ids never contain the separator
+        match id.rfind(&self.urls.path_sep) {
+            Some(position) => &id[..position],
+            None => "",
+        }
+    }
+}
+
+/// Ids of all directories below `root`. Hidden directories and symbolic
+/// links are skipped.
+pub fn directory_ids(root: &Path, urls: &Urls) -> Vec<String> {
+    let mut found = Vec::new();
+    collect_directories(root, urls, "", 0, &mut found);
+    let mut ids: Vec<String> = found.into_iter().map(|(id, _)| id).collect();
+    ids.sort();
+    ids
+}
+
+fn collect_directories(
+    path: &Path,
+    urls: &Urls,
+    id: &str,
+    depth: usize,
+    found: &mut Vec<(String, PathBuf)>,
+) {
+    if depth >= MAX_DEPTH {
+        return;
+    }
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => (),
+            _ => continue,
+        }
+        let name = match entry.file_name().into_string() {
+            Ok(name) if !name.starts_with('.') => name,
+            _ => continue,
+        };
+        let child = urls.join(id, &urls.escape(&name));
+        collect_directories(&entry.path(), urls, &child, depth + 1, found);
+        found.push((child, entry.path()));
+    }
+}
+
+fn warm_collection(id: &str, forwarded: Option<&Urls>, services: &Services) {
+    let validator = match services.image_source.collection_validator(id) {
+        Ok(validator) => variant(validator, forwarded),
+        Err(_) => return,
+    };
+    let iiif_generator = &services.iiif_generator;
+    let urls = forwarded.unwrap_or_else(|| iiif_generator.urls());
+    match collection_json(iiif_generator, id, urls) {
+        Ok(json) => services.documents.insert(
+            document_key(id, "collection", forwarded),
+            validator.tag,
+            json.clone(),
+            json.len(),
+        ),
+        Err(e) => println!("Could not generate collection for {}: {}", id, e),
+    }
+}
+
+/// Generates the manifest of a directory with the configured or forwarded
+/// URLs, so the next request is served from the cache.
+fn warm_manifest(id: &str, forwarded: Option<&Urls>, services: &Services) {
+    let image_source = &services.image_source;
+    let validator = match image_source.manifest_validator(id) {
+        Ok(validator) => variant(validator, forwarded),
+        Err(_) => return,
+    };
+    let iiif_generator = &services.iiif_generator;
+    let urls = forwarded.unwrap_or_else(|| iiif_generator.urls());
+    match manifest_json(image_source, iiif_generator, id, urls) {
+        Ok(json) => services.documents.insert(
+            document_key(id, "manifest", forwarded),
+            validator.tag,
+            json.clone(),
+            json.len(),
+        ),
+        Err(e) => println!("Could not generate manifest for {}: {}", id, e),
+    }
+}
+
+/// Ids that were added, removed or changed between two scans.
+fn changes(before: &HashMap<String, String>, after: &HashMap<String, String>) -> Vec<String> {
+    let mut ids: Vec<String> = after
+        .iter()
+        .filter(|(id, tag)| before.get(*id) != Some(tag))
+        .map(|(id, _)| id.clone())
+        .chain(before.keys().filter(|id| !after.contains_key(*id)).cloned())
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Urls, WatchMode};
+    use crate::watch::{changes, Watcher};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn finds_directories_and_changes() {
+        let root = std::env::temp_dir().join(format!("forager-watch-{}", std::process::id()));
+        for path in &["a/b", "a/.hidden", "c", "d-e"] {
+            std::fs::create_dir_all(root.join(path)).unwrap();
+        }
+        let watcher = Watcher {
+            root: root.clone(),
+            urls: Urls {
+                path_sep: "-".to_owned(),
+                image_api: String::new(),
+                presentation_api: String::new(),
+                page_id: String::new(),
+            },
+            mode: WatchMode::Poll,
+            interval: Duration::from_secs(1),
+            prewarm: false,
+            seen: HashMap::new(),
+        };
+        let ids = watcher.ids();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(ids, vec!["a", "a-b", "c", "d%2De"]);
+        assert_eq!(watcher.parent("a-b"), "a");
+        assert_eq!(watcher.parent("a"), "");
+
+        let tags = |entries: &[(&str, &str)]| -> HashMap<String, String> {
+            entries
+                .iter()
+                .map(|(id, tag)| (id.to_string(), tag.to_string()))
+                .collect()
+        };
+        let before = tags(&[("a", "1"), ("a-b", "1"), ("c", "1")]);
+        let after = tags(&[("a", "1"), ("a-b", "2"), ("x", "1")]);
+        assert_eq!(changes(&before, &after), vec!["a-b", "c", "x"]);
+    }
+}